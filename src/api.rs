@@ -0,0 +1,129 @@
+use std::time::Instant;
+
+use crate::{model::ClockQuery, time_source, ClockRegistry, ConnectionCounter};
+
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{SecondsFormat, TimeDelta};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+static STARTED: Lazy<Instant> = Lazy::new(Instant::now);
+
+#[derive(Debug, Serialize)]
+struct Now {
+    unix_ms: i64,
+    iso8601: String,
+    tz: &'static str,
+    zone: String,
+    uptime_ms: u128,
+    connection_count: usize,
+}
+
+/// The time at the moment of the request to the millisecond, rather than the tick the streams show.
+pub async fn now_handler(
+    Query(query): Query<ClockQuery>,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
+) -> impl IntoResponse {
+    let utc = time_source::now();
+    let tz = clocks.key(query.tz).tz;
+
+    let now = Now {
+        unix_ms: utc.timestamp_millis(),
+        iso8601: utc
+            .with_timezone(&tz)
+            .to_rfc3339_opts(SecondsFormat::Millis, false),
+        tz: tz.name(),
+        zone: clocks.zone_label(tz, utc),
+        uptime_ms: STARTED.elapsed().as_millis(),
+        connection_count: counter.current(),
+    };
+
+    ([(header::CACHE_CONTROL, "no-store")], Json(now))
+}
+
+#[derive(Debug, Serialize)]
+struct TimeSource {
+    /// `None` when the local clock is used as is.
+    server: Option<&'static str>,
+    offset_ms: f64,
+    delay_ms: Option<f64>,
+    last_sync: Option<String>,
+}
+
+fn milliseconds(delta: TimeDelta) -> f64 {
+    delta.num_microseconds().unwrap_or_default() as f64 / 1000.0
+}
+
+/// Which clock the editions show and how far it's from the local one.
+pub async fn time_source_handler() -> impl IntoResponse {
+    let sync = time_source::last_sync();
+
+    let time_source = TimeSource {
+        server: time_source::server(),
+        offset_ms: milliseconds(time_source::offset()),
+        delay_ms: sync.map(|sync| milliseconds(sync.delay)),
+        last_sync: sync.map(|sync| sync.utc.to_rfc3339_opts(SecondsFormat::Millis, true)),
+    };
+
+    ([(header::CACHE_CONTROL, "no-store")], Json(time_source))
+}
+
+/// Active streams by edition and by timezone.
+pub async fn stats_handler(
+    State((_, counter)): State<(ClockRegistry, ConnectionCounter)>,
+) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(counter.breakdown()),
+    )
+}
+
+/// Every name `tz` accepts.
+pub async fn zones_handler() -> impl IntoResponse {
+    let zones: Vec<&str> = chrono_tz::TZ_VARIANTS.iter().map(|tz| tz.name()).collect();
+    Json(zones)
+}
+
+/// Starts the uptime, which otherwise starts with the first request.
+pub fn initialization() {
+    Lazy::force(&STARTED);
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+
+    use super::*;
+    use crate::clock_registry::tests::registry;
+
+    #[tokio::test]
+    async fn now_is_precise_to_the_millisecond_and_not_cached() {
+        let query = ClockQuery {
+            tz: Some(chrono_tz::Asia::Tokyo),
+            precision: None,
+        };
+        let state = (registry(), ConnectionCounter::new(Default::default()));
+
+        let response = now_handler(Query(query), State(state))
+            .await
+            .into_response();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let now: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(now["tz"], "Asia/Tokyo");
+        assert_eq!(now["zone"], "JST");
+
+        let iso8601 = now["iso8601"].as_str().unwrap();
+        let utc = chrono::DateTime::parse_from_rfc3339(iso8601).unwrap();
+        assert!(iso8601.ends_with("+09:00"), "{iso8601}");
+        assert_eq!(iso8601.split_once('.').unwrap().1.len(), "123+09:00".len());
+        assert_eq!(now["unix_ms"], utc.timestamp_millis());
+    }
+}
@@ -0,0 +1,382 @@
+use std::io::Cursor;
+
+use crate::{
+    connection_counter::Edition,
+    font,
+    gif_banner::{banner_text, current_text, BannerQuery, Palette, INK_COLORS},
+    layout::{changed_cells, upscale, Layout},
+    limits::Admission,
+    metrics,
+    model::{Context, Editions},
+    mygif::{Position, Size},
+    mypng::{
+        do_deflate, AnimationControl, BlendOp, Chunk, DisposeOp, FrameControl, FrameData,
+        ImageHeader, COLOR_TYPE_INDEXED, MAX_FRAMES, SIGNATURE,
+    },
+    peer::Peer,
+    Clock, ClockRegistry, ConnectionCounter,
+};
+
+use async_stream::try_stream;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, HeaderName},
+    response::IntoResponse,
+};
+use binrw::BinWrite;
+use bytes::Bytes;
+use futures::Stream;
+
+/// A deflated region of the banner, which still needs its sequence numbers.
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    pub position: Position,
+    pub size: Size,
+    pub data: Bytes,
+}
+
+/// Paints the banner with the same glyphs and color indexes as the GIF banner.
+fn canvas(ctx: &Context) -> Vec<u8> {
+    let text = current_text(ctx);
+
+    let canvas = Layout::centered(font::get(), ctx.banner.size, &text)
+        .paint(ctx.banner.size, |ink| 1 + (ink % INK_COLORS) as u8);
+
+    upscale(&canvas, ctx.banner.size, ctx.banner.scale)
+}
+
+fn crop(pixels: &[u8], screen: Size, position: Position, size: Size) -> Frame {
+    let (left, width) = (position.left as usize, size.width as usize);
+
+    let region: Vec<u8> = pixels
+        .chunks(screen.width as usize)
+        .skip(position.top as usize)
+        .take(size.height as usize)
+        .flat_map(|row| &row[left..left + width])
+        .copied()
+        .collect();
+
+    Frame {
+        position,
+        size,
+        data: Bytes::from(do_deflate(&region, width)),
+    }
+}
+
+/// Redraws the smallest rectangle containing every glyph that changed since the previous tick.
+///
+/// `None` if nothing changed, a keyframe when the text moved.
+pub fn encode(ctx: &Context) -> Option<Frame> {
+    if ctx.reload_after.is_some() {
+        return Some(encode_keyframe(ctx));
+    }
+
+    let font = font::get();
+
+    let text = banner_text(ctx.local_time(), ctx.zone_label());
+    let previous_text = banner_text(ctx.previous_local_time(), ctx.previous_zone_label());
+
+    let Some(changed) = changed_cells(font, ctx.banner.size, &previous_text, &text) else {
+        return Some(encode_keyframe(ctx));
+    };
+
+    let changed: Vec<Position> = changed.into_iter().map(|cell| cell.position).collect();
+
+    let left = changed.iter().map(|p| p.left).min()?;
+    let top = changed.iter().map(|p| p.top).min()?;
+    let right = changed.iter().map(|p| p.left + font.size.width).max()?;
+    let bottom = changed.iter().map(|p| p.top + font.size.height).max()?;
+
+    let scale = ctx.banner.scale;
+
+    Some(crop(
+        &canvas(ctx),
+        ctx.banner.screen_size(),
+        Position::new(left * scale, top * scale),
+        Size::new((right - left) * scale, (bottom - top) * scale),
+    ))
+}
+
+/// The whole banner.
+pub fn encode_keyframe(ctx: &Context) -> Frame {
+    let screen = ctx.banner.screen_size();
+    crop(&canvas(ctx), screen, Position::new(0, 0), screen)
+}
+
+/// Appends `frame` to a stream which has used the sequence numbers up to `sequence`.
+///
+/// The first frame is the default image and goes to `IDAT`, all others to `fdAT`.
+fn frame_chunks(frame: &Frame, sequence: &mut u32) -> Bytes {
+    let mut buffer = Cursor::new(vec![]);
+
+    let control = Chunk::new(
+        b"fcTL",
+        &FrameControl {
+            sequence_number: *sequence,
+            width: frame.size.width.into(),
+            height: frame.size.height.into(),
+            x_offset: frame.position.left.into(),
+            y_offset: frame.position.top.into(),
+            delay_num: 2,
+            delay_den: 100,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        },
+    );
+
+    let data = if *sequence == 0 {
+        *sequence += 1;
+
+        Chunk {
+            kind: *b"IDAT",
+            data: frame.data.to_vec(),
+        }
+    } else {
+        *sequence += 2;
+
+        Chunk::new(
+            b"fdAT",
+            &FrameData {
+                sequence_number: *sequence - 1,
+                data: frame.data.to_vec(),
+            },
+        )
+    };
+
+    [control, data].write_be(&mut buffer).unwrap();
+
+    Bytes::from(buffer.into_inner())
+}
+
+/// `IHDR`, `PLTE` and `tRNS`, which a still image of the banner shares with the animation.
+pub fn image_chunks(size: Size, palette: &Palette, alpha: u8) -> [Chunk; 3] {
+    [
+        Chunk::new(
+            b"IHDR",
+            &ImageHeader {
+                width: size.width.into(),
+                height: size.height.into(),
+                bit_depth: 8,
+                color_type: COLOR_TYPE_INDEXED,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method: 0,
+            },
+        ),
+        Chunk::new(b"PLTE", &palette.global_color_table()),
+        // Only the background may be translucent, the inks keep the implicit 255.
+        Chunk::new(b"tRNS", &vec![alpha]),
+    ]
+}
+
+fn header(size: Size, palette: &Palette, alpha: u8) -> Bytes {
+    let mut buffer = Cursor::new(vec![]);
+
+    let control = Chunk::new(
+        b"acTL",
+        &AnimationControl {
+            num_frames: MAX_FRAMES,
+            num_plays: 1,
+        },
+    );
+
+    SIGNATURE.write_be(&mut buffer).unwrap();
+    image_chunks(size, palette, alpha)
+        .write_be(&mut buffer)
+        .unwrap();
+    control.write_be(&mut buffer).unwrap();
+
+    Bytes::from(buffer.into_inner())
+}
+
+fn stream(
+    mut clock: Clock,
+    counter: ConnectionCounter,
+    header: Bytes,
+) -> impl Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Send + Sync>>> {
+    try_stream! {
+        let _session = counter.acquire(Edition::Png, clock.key().tz);
+        let mut sequence = 0;
+
+        yield header;
+
+        let (keyframes, mut timestamp) = {
+            let data = clock.borrow_and_update();
+            (data.keyframes.clone(), data.timestamp)
+        };
+
+        yield frame_chunks(&keyframes.png(), &mut sequence);
+
+        loop {
+            if clock.changed().await.is_err() {
+                break;
+            }
+
+            let frame = {
+                let data = clock.borrow_and_update();
+
+                // The delta only applies on top of the frame we sent last.
+                let frame = if data.previous_timestamp == timestamp {
+                    Ok(data.png.clone())
+                } else {
+                    Err(data.keyframes.clone())
+                };
+
+                timestamp = data.timestamp;
+                frame
+            };
+
+            // Rendered after letting go of the frame, so that the ticker can publish meanwhile.
+            if let Some(frame) = frame.unwrap_or_else(|keyframes| Some(keyframes.png())) {
+                yield frame_chunks(&frame, &mut sequence);
+            }
+        }
+
+        let mut end = Cursor::new(vec![]);
+        Chunk { kind: *b"IEND", data: vec![] }.write_be(&mut end).unwrap();
+        yield Bytes::from(end.into_inner());
+    }
+}
+
+pub async fn png_handler(
+    headers: HeaderMap,
+    Query(query): Query<BannerQuery>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    admission: Admission,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
+) -> impl IntoResponse {
+    let key = query.key(&clocks, Editions::PngBanner);
+
+    let stream = stream(
+        clocks.subscribe(key, &peer, admission.deadline()),
+        counter,
+        header(
+            key.banner.screen_size(),
+            &query.palette(),
+            query.alpha.unwrap_or(0xFF),
+        ),
+    );
+    let body = Body::from_stream(metrics::count_bytes(Edition::Png, admission.limit(stream)));
+
+    let is_cloudflare = headers.contains_key("cf-ray");
+
+    let headers = [
+        (
+            header::CONTENT_TYPE,
+            if is_cloudflare {
+                "application/grpc"
+            } else {
+                "image/png"
+            },
+        ),
+        (
+            HeaderName::from_static("x-original-content-type"),
+            "image/png",
+        ),
+    ];
+
+    (headers, body)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use binrw::BinRead;
+
+    use super::*;
+    use crate::{
+        gif_banner::tests::{palette, setup, BannerFormat},
+        mygif::Color,
+        mypng::undo_deflate,
+    };
+
+    pub(crate) const APNG: BannerFormat = BannerFormat {
+        header: |banner| header(banner.screen_size(), &palette(), 0xFF).to_vec(),
+        keyframe: |ctx, sequence| frame_chunks(&encode_keyframe(ctx), sequence).to_vec(),
+        delta: |ctx, sequence| {
+            encode(ctx)
+                .map(|frame| frame_chunks(&frame, sequence).to_vec())
+                .unwrap_or_default()
+        },
+        composite: |stream, screen| composite(&chunks(stream), screen),
+    };
+
+    /// Reads every chunk after the signature, checking CRCs along the way.
+    fn chunks(stream: &[u8]) -> Vec<Chunk> {
+        assert_eq!(stream[..SIGNATURE.len()], SIGNATURE);
+
+        let mut reader = Cursor::new(&stream[SIGNATURE.len()..]);
+        let mut chunks = vec![];
+
+        while reader.position() < reader.get_ref().len() as u64 {
+            chunks.push(Chunk::read(&mut reader).unwrap());
+        }
+
+        chunks
+    }
+
+    /// Paints every frame onto one canvas, checking the sequence numbers.
+    fn composite(chunks: &[Chunk], screen: Size) -> Vec<u8> {
+        let width = screen.width as usize;
+        let mut canvas = vec![0u8; width * screen.height as usize];
+        let mut control: Option<FrameControl> = None;
+        let mut sequence = 0;
+
+        for chunk in chunks {
+            let data = match &chunk.kind {
+                b"fcTL" => {
+                    let frame = FrameControl::read(&mut Cursor::new(&chunk.data)).unwrap();
+                    assert_eq!(frame.sequence_number, sequence);
+                    sequence += 1;
+                    control = Some(frame);
+                    continue;
+                }
+                b"IDAT" => chunk.data.clone(),
+                b"fdAT" => {
+                    let frame = FrameData::read(&mut Cursor::new(&chunk.data)).unwrap();
+                    assert_eq!(frame.sequence_number, sequence);
+                    sequence += 1;
+                    frame.data
+                }
+                _ => continue,
+            };
+
+            let frame = control.take().expect("frame data without fcTL");
+            let frame_width = frame.width as usize;
+
+            for (i, v) in undo_deflate(&data, frame_width)
+                .unwrap()
+                .into_iter()
+                .enumerate()
+            {
+                let x = frame.x_offset as usize + i % frame_width;
+                let y = frame.y_offset as usize + i / frame_width;
+                canvas[y * width + x] = v;
+            }
+        }
+
+        canvas
+    }
+
+    #[test]
+    fn header_declares_palette_and_alpha() {
+        setup();
+
+        let chunks = chunks(&header(Size::new(88, 31), &palette(), 0x40));
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|chunk| &chunk.kind).collect();
+
+        assert_eq!(kinds, [b"IHDR", b"PLTE", b"tRNS", b"acTL"]);
+
+        let ihdr = ImageHeader::read(&mut Cursor::new(&chunks[0].data)).unwrap();
+        assert_eq!((ihdr.width, ihdr.height), (88, 31));
+
+        let plte: Vec<Color> = chunks[1]
+            .data
+            .chunks(3)
+            .map(|rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]))
+            .collect();
+
+        assert_eq!(plte, palette().global_color_table());
+        assert_eq!(chunks[2].data, [0x40]);
+    }
+}
@@ -0,0 +1,653 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use chrono_tz::Tz;
+use tokio::{sync::watch, time::Instant};
+
+use crate::{
+    connection_counter::ConnectionCounter,
+    metrics,
+    model::{Banner, ClockData, ClockKey, Context, Editions, Precision, Tick},
+    mygif::Size,
+    peer::Peer,
+    time_source,
+};
+
+/// Upper bound of the one-way latency `Lead::compensate_rtt` makes up for.
+const MAX_RTT_LEAD: Duration = Duration::from_millis(250);
+
+/// How soon a subscriber whose lifetime is over is told to come back.
+const EXPIRED_RELOAD_AFTER: Duration = Duration::from_secs(1);
+
+/// How much less than the period of a clock its lead is at most.
+const LEAD_MARGIN: Duration = Duration::from_millis(1);
+
+/// Banner geometries rendered at once, subscribers asking for yet another get the default one.
+const MAX_BANNER_GEOMETRIES: usize = 16;
+
+/// How long before its second a frame is sent.
+#[derive(Debug, Clone, Copy)]
+pub struct Lead {
+    pub base: Duration,
+    /// Additionally sends half of the round trip time of each connection earlier.
+    pub compensate_rtt: bool,
+}
+
+impl Lead {
+    /// When the ticker has to publish so that every connection can get its frame in time.
+    ///
+    /// Less than `period`, the one of the finest clock, so that no frame is published before
+    /// the previous one is due.
+    pub fn max(&self, period: TimeDelta) -> Duration {
+        let max = match self.compensate_rtt {
+            true => self.base + MAX_RTT_LEAD,
+            false => self.base,
+        };
+
+        max.min(longest_lead(period))
+    }
+
+    /// Less than `period` like `Lead::max`, so that a subscriber sees every frame it waits for.
+    fn for_peer(&self, peer: &Peer, period: TimeDelta) -> Duration {
+        let lead = match (self.compensate_rtt, peer.rtt) {
+            (true, Some(rtt)) => self.base + (rtt / 2).min(MAX_RTT_LEAD),
+            _ => self.base,
+        };
+
+        lead.min(longest_lead(period))
+    }
+}
+
+fn longest_lead(period: TimeDelta) -> Duration {
+    period.to_std().unwrap().saturating_sub(LEAD_MARGIN)
+}
+
+/// A subscription to one `ClockKey`, which sees each frame `lead` before its second begins.
+pub struct Clock {
+    key: ClockKey,
+    receiver: watch::Receiver<ClockData>,
+    lead: Duration,
+    /// When the subscription ends with a farewell frame of its own, see `Clock::expire`.
+    deadline: Option<Instant>,
+    is_expired: bool,
+    registry: ClockRegistry,
+}
+
+impl Clock {
+    /// Waits for the next frame and then until it's due for this subscriber.
+    ///
+    /// Cancel safe like `watch::Receiver::changed`, the frame isn't marked as seen until it's due.
+    /// Fails once the registry is closed or the deadline passed, and the farewell frame was seen.
+    pub async fn changed(&mut self) -> Result<(), watch::error::RecvError> {
+        let Some(deadline) = self.deadline else {
+            return self.next().await;
+        };
+
+        let is_expired = tokio::select! {
+            changed = self.next() => {
+                changed?;
+                false
+            }
+            _ = tokio::time::sleep_until(deadline) => true,
+        };
+
+        if is_expired {
+            self.expire();
+        }
+
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<(), watch::error::RecvError> {
+        self.receiver.clone().changed().await?;
+
+        let timestamp = self.receiver.borrow().timestamp;
+        let due = DateTime::from_timestamp_millis(timestamp).unwrap_or_default();
+
+        if let Ok(wait) = (due - self.lead - time_source::now()).to_std() {
+            tokio::time::sleep(wait).await;
+        }
+
+        self.receiver.mark_unchanged();
+        Ok(())
+    }
+
+    /// Swaps the subscription for a farewell frame, like the one `ClockRegistry::close` sends
+    /// everyone, so that the stream ends the same way.
+    fn expire(&mut self) {
+        let previous_timestamp = self.receiver.borrow().timestamp;
+        let farewell = self
+            .registry
+            .farewell(self.key, previous_timestamp, EXPIRED_RELOAD_AFTER);
+
+        // Already seen, and without a sender, so the next `changed` fails.
+        self.receiver = watch::channel(farewell).1;
+        self.deadline = None;
+        self.is_expired = true;
+    }
+
+    /// Whether the subscription ended because of its deadline rather than a restart.
+    pub fn is_expired(&self) -> bool {
+        self.is_expired
+    }
+
+    pub fn key(&self) -> ClockKey {
+        self.key
+    }
+
+    pub fn borrow(&self) -> watch::Ref<'_, ClockData> {
+        self.receiver.borrow()
+    }
+
+    pub fn borrow_and_update(&mut self) -> watch::Ref<'_, ClockData> {
+        self.receiver.borrow_and_update()
+    }
+
+    pub fn mark_unchanged(&mut self) {
+        self.receiver.mark_unchanged();
+    }
+}
+
+/// Shares the rendered frames of each `ClockKey` between all of its subscribers.
+#[derive(Clone)]
+pub struct ClockRegistry {
+    default_tz: Tz,
+    banner_size: Size,
+    time_format: Arc<str>,
+    zone_label: Option<Arc<str>>,
+    lead: Lead,
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    last_tick: Tick,
+    clocks: HashMap<ClockKey, watch::Sender<ClockData>>,
+    is_closed: bool,
+}
+
+impl ClockRegistry {
+    pub fn new(
+        default_tz: Tz,
+        time_format: &str,
+        zone_label: Option<&str>,
+        banner_size: Size,
+        lead: Lead,
+        tick: Tick,
+    ) -> Self {
+        ClockRegistry {
+            default_tz,
+            banner_size,
+            time_format: time_format.into(),
+            zone_label: zone_label.map(Into::into),
+            lead,
+            inner: Arc::new(Mutex::new(Inner {
+                last_tick: tick,
+                clocks: HashMap::new(),
+                is_closed: false,
+            })),
+        }
+    }
+
+    /// `previous_timestamp` is of the frame subscribers of `key` currently have, 0 if none.
+    fn render(&self, key: ClockKey, tick: &Tick, previous_timestamp: i64) -> ClockData {
+        crate::encode(&Context {
+            previous_timestamp,
+            timestamp: tick.utc.timestamp_millis(),
+            connections: tick.connections.clone(),
+            utc: tick.utc,
+            tz: key.tz,
+            editions: key.editions,
+            banner: key.banner,
+            precision: key.precision,
+            time_format: self.time_format.clone(),
+            sync: tick.sync,
+            reload_after: tick.reload_after,
+            zone_label: self
+                .zone_label
+                .clone()
+                .filter(|_| key.tz == self.default_tz),
+        })
+    }
+
+    pub fn time_format(&self) -> &str {
+        &self.time_format
+    }
+
+    /// What the editions show as the zone of `tz` at `utc`.
+    pub fn zone_label(&self, tz: Tz, utc: DateTime<Utc>) -> String {
+        match self.zone_label.as_deref().filter(|_| tz == self.default_tz) {
+            Some(label) => label.to_string(),
+            None => utc.with_timezone(&tz).format("%Z").to_string(),
+        }
+    }
+
+    /// The key of a subscriber which only chose a timezone, if any.
+    pub fn key(&self, tz: Option<Tz>) -> ClockKey {
+        ClockKey {
+            tz: tz.unwrap_or(self.default_tz),
+            editions: Editions::Pages,
+            banner: Banner {
+                size: self.banner_size,
+                scale: 1,
+            },
+            precision: Precision::Second,
+        }
+    }
+
+    /// The key of a banner subscriber, `banner` unless too many other geometries are rendered.
+    pub fn banner_key(&self, tz: Option<Tz>, editions: Editions, banner: Banner) -> ClockKey {
+        let default = self.key(tz);
+
+        let geometries: HashSet<Banner> = self
+            .inner
+            .lock()
+            .unwrap()
+            .clocks
+            .keys()
+            .filter(|key| key.editions != Editions::Pages && key.banner != default.banner)
+            .map(|key| key.banner)
+            .collect();
+
+        let is_allowed = banner == default.banner
+            || geometries.contains(&banner)
+            || geometries.len() < MAX_BANNER_GEOMETRIES;
+
+        ClockKey {
+            editions,
+            banner: if is_allowed { banner } else { default.banner },
+            ..default
+        }
+    }
+
+    /// The precision of the fastest key, `Second` if there are none.
+    pub fn finest_precision(&self) -> Precision {
+        let inner = self.inner.lock().unwrap();
+
+        match inner
+            .clocks
+            .keys()
+            .any(|key| key.precision == Precision::Decisecond)
+        {
+            true => Precision::Decisecond,
+            false => Precision::Second,
+        }
+    }
+
+    /// Renders the first frame of a new key outside the lock, so that others don't wait for it.
+    fn receiver(&self, key: ClockKey) -> watch::Receiver<ClockData> {
+        let tick = {
+            let inner = self.inner.lock().unwrap();
+
+            match inner.clocks.get(&key) {
+                Some(sender) if !inner.is_closed => return sender.subscribe(),
+                _ => inner.last_tick.clone(),
+            }
+        };
+
+        let data = self.render(key, &tick, 0);
+        let mut inner = self.inner.lock().unwrap();
+
+        // Only the farewell, from a sender which is already gone.
+        if inner.is_closed {
+            return watch::channel(data).1;
+        }
+
+        // Another subscriber may have been quicker, its frame is as good.
+        inner
+            .clocks
+            .entry(key)
+            .or_insert_with(|| watch::channel(data).0)
+            .subscribe()
+    }
+
+    /// Ends the subscription at `deadline`, if any.
+    pub fn subscribe(&self, key: ClockKey, peer: &Peer, deadline: Option<Instant>) -> Clock {
+        Clock {
+            key,
+            receiver: self.receiver(key),
+            lead: self.lead.for_peer(peer, key.precision.period()),
+            deadline,
+            is_expired: false,
+            registry: self.clone(),
+        }
+    }
+
+    /// The frame published last, for a single response rather than a stream.
+    ///
+    /// Keys nobody subscribed to are rendered once and not kept.
+    pub fn latest(&self, key: ClockKey) -> ClockData {
+        let tick = {
+            let inner = self.inner.lock().unwrap();
+
+            match inner.clocks.get(&key) {
+                Some(sender) => return sender.borrow().clone(),
+                None => inner.last_tick.clone(),
+            }
+        };
+
+        self.render(key, &tick, 0)
+    }
+
+    /// Renders the next frame for every key that currently has subscribers and is due at `tick`.
+    pub fn render_all(&self, tick: &Tick) -> HashMap<ClockKey, ClockData> {
+        let keys: Vec<(ClockKey, i64)> = self
+            .inner
+            .lock()
+            .unwrap()
+            .clocks
+            .iter()
+            .filter(|(key, _)| key.precision.is_due(tick.utc))
+            .map(|(key, sender)| (*key, sender.borrow().timestamp))
+            .collect();
+
+        keys.into_iter()
+            .map(|(key, previous_timestamp)| (key, self.render(key, tick, previous_timestamp)))
+            .collect()
+    }
+
+    /// Publishes pre-rendered frames and forgets keys nobody watches anymore.
+    ///
+    /// Keys subscribed after `render_all` was called are rendered here so that they don't miss a
+    /// tick, outside the lock like the others.
+    pub fn publish(&self, tick: Tick, mut frames: HashMap<ClockKey, ClockData>) {
+        loop {
+            let mut inner = self.inner.lock().unwrap();
+
+            if inner.is_closed {
+                return;
+            }
+
+            let missing: Vec<(ClockKey, i64)> = inner
+                .clocks
+                .iter()
+                .filter(|(key, _)| key.precision.is_due(tick.utc) && !frames.contains_key(key))
+                .map(|(key, sender)| (*key, sender.borrow().timestamp))
+                .collect();
+
+            if missing.is_empty() {
+                inner.last_tick = tick.clone();
+                inner
+                    .clocks
+                    .retain(|_, sender| sender.receiver_count() != 0);
+
+                for (key, sender) in &inner.clocks {
+                    if let Some(data) = frames.remove(key) {
+                        sender.send_replace(data);
+                    }
+                }
+
+                return;
+            }
+
+            drop(inner);
+
+            for (key, previous_timestamp) in missing {
+                frames.insert(key, self.render(key, &tick, previous_timestamp));
+            }
+        }
+    }
+
+    /// A last frame for a single subscriber of `key`, telling to reload after `reload_after`.
+    fn farewell(
+        &self,
+        key: ClockKey,
+        previous_timestamp: i64,
+        reload_after: Duration,
+    ) -> ClockData {
+        let tick = Tick {
+            utc: time_source::now(),
+            reload_after: Some(reload_after.as_secs()),
+            ..self.inner.lock().unwrap().last_tick.clone()
+        };
+
+        self.render(key, &tick, previous_timestamp)
+    }
+
+    /// Renders and publishes a frame for every tick, ahead by the lead, forever.
+    pub async fn run(&self, counter: ConnectionCounter) {
+        let mut due = time_source::now()
+            .duration_trunc(Precision::FINEST)
+            .unwrap();
+
+        loop {
+            // Ticks every second unless someone asked for more.
+            let period = self.finest_precision().period();
+            let lead = TimeDelta::from_std(self.lead.max(period)).unwrap();
+
+            // The next tick, or the one after now if the ticker fell behind or a sync moved
+            // the clock back, without skipping or repeating any while on schedule.
+            let next = time_source::now().duration_trunc(period).unwrap() + period;
+            due = (due.duration_trunc(period).unwrap() + period).clamp(next, next + lead + period);
+
+            let tick = Tick {
+                utc: due,
+                connections: Arc::new(counter.breakdown()),
+                sync: time_source::last_sync(),
+                reload_after: None,
+            };
+
+            let frames = self.render_all(&tick);
+
+            if let Ok(wait) = (due - lead - time_source::now()).to_std() {
+                tokio::time::sleep(wait).await;
+            }
+
+            let lateness = time_source::now() - (due - lead);
+            metrics::tick_published(lateness.to_std().unwrap_or_default());
+
+            self.publish(tick, frames);
+        }
+    }
+
+    /// Sends every subscriber a last frame telling to reload after `reload_after` and ends
+    /// their streams, as the server is going away.
+    pub fn close(&self, reload_after: Duration) {
+        let (tick, clocks) = {
+            let mut inner = self.inner.lock().unwrap();
+
+            inner.last_tick = Tick {
+                utc: time_source::now(),
+                reload_after: Some(reload_after.as_secs()),
+                ..inner.last_tick.clone()
+            };
+            inner.is_closed = true;
+
+            (inner.last_tick.clone(), std::mem::take(&mut inner.clocks))
+        };
+
+        // Dropping the senders afterwards ends every stream once it has seen the farewell.
+        for (key, sender) in clocks {
+            let previous_timestamp = sender.borrow().timestamp;
+            sender.send_replace(self.render(key, &tick, previous_timestamp));
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::gif_banner::tests::setup;
+
+    pub(crate) fn registry() -> ClockRegistry {
+        registry_with(Lead {
+            base: Duration::ZERO,
+            compensate_rtt: false,
+        })
+    }
+
+    fn registry_with(lead: Lead) -> ClockRegistry {
+        setup();
+
+        ClockRegistry::new(
+            chrono_tz::UTC,
+            "%Y-%m-%d %H:%M:%S",
+            None,
+            Size::new(88, 31),
+            lead,
+            Tick {
+                utc: Utc::now(),
+                connections: Default::default(),
+                sync: None,
+                reload_after: None,
+            },
+        )
+    }
+
+    pub(crate) fn peer() -> Peer {
+        Peer {
+            addr: "127.0.0.1:1".parse().unwrap(),
+            rtt: None,
+        }
+    }
+
+    #[test]
+    fn leads_make_up_for_half_the_round_trip_up_to_a_cap() {
+        let second = TimeDelta::seconds(1);
+        let ms = Duration::from_millis;
+        let with_rtt = |rtt| Peer {
+            rtt: Some(ms(rtt)),
+            ..peer()
+        };
+
+        let fixed = Lead {
+            base: ms(100),
+            compensate_rtt: false,
+        };
+        assert_eq!(fixed.for_peer(&with_rtt(200), second), ms(100));
+        assert_eq!(fixed.max(second), ms(100));
+
+        let compensated = Lead {
+            compensate_rtt: true,
+            ..fixed
+        };
+        assert_eq!(compensated.for_peer(&peer(), second), ms(100));
+        assert_eq!(compensated.for_peer(&with_rtt(200), second), ms(200));
+        assert_eq!(
+            compensated.for_peer(&with_rtt(2000), second),
+            ms(100) + MAX_RTT_LEAD
+        );
+        assert_eq!(compensated.max(second), ms(100) + MAX_RTT_LEAD);
+
+        // Never a whole period, however long the round trip.
+        let decisecond = TimeDelta::milliseconds(100);
+        assert_eq!(compensated.for_peer(&with_rtt(200), decisecond), ms(99));
+        assert_eq!(compensated.max(decisecond), ms(99));
+    }
+
+    #[test]
+    fn keys_only_render_their_editions() {
+        let clocks = registry();
+        let default = clocks.key(None).banner;
+
+        let page = clocks.subscribe(clocks.key(None), &peer(), None);
+        assert!(!page.borrow().html.is_empty());
+        assert!(page.borrow().gif.is_empty());
+
+        let gif = clocks.subscribe(
+            clocks.banner_key(None, Editions::GifBanner, default),
+            &peer(),
+            None,
+        );
+        assert!(!gif.borrow().gif.is_empty());
+        assert!(gif.borrow().png.is_none());
+        assert!(gif.borrow().html.is_empty());
+
+        let png = clocks.subscribe(
+            clocks.banner_key(None, Editions::PngBanner, default),
+            &peer(),
+            None,
+        );
+        assert!(png.borrow().png.is_some());
+        assert!(png.borrow().gif.is_empty());
+        assert!(!png.borrow().keyframes.png().data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn deciseconds_arrive_one_by_one_despite_a_long_lead() {
+        let clocks = registry_with(Lead {
+            base: Duration::from_millis(200),
+            compensate_rtt: true,
+        });
+
+        let key = ClockKey {
+            precision: Precision::Decisecond,
+            ..clocks.key(None)
+        };
+        let peer = Peer {
+            rtt: Some(Duration::from_millis(400)),
+            ..peer()
+        };
+        let mut clock = clocks.subscribe(key, &peer, None);
+
+        tokio::spawn({
+            let clocks = clocks.clone();
+            async move { clocks.run(ConnectionCounter::new(Default::default())).await }
+        });
+
+        // Follows the frame rendered on subscription, which is off the schedule.
+        clock.changed().await.unwrap();
+        let mut previous = clock.borrow_and_update().timestamp;
+
+        for _ in 0..8 {
+            clock.changed().await.unwrap();
+            let timestamp = clock.borrow_and_update().timestamp;
+            let early = timestamp - time_source::now().timestamp_millis();
+
+            assert_eq!(timestamp - previous, 100);
+            assert!(early < 100, "{early}ms early");
+
+            previous = timestamp;
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_subscriptions_end_with_a_farewell() {
+        let clocks = registry();
+        let mut clock = clocks.subscribe(clocks.key(None), &peer(), Some(Instant::now()));
+
+        clock.changed().await.unwrap();
+        assert!(clock.is_expired());
+        assert_eq!(clock.borrow_and_update().reload_after, Some(1));
+        assert!(clock.changed().await.is_err());
+
+        // Everyone else keeps their frames.
+        let other = clocks.subscribe(clocks.key(None), &peer(), None);
+        assert_eq!(other.borrow().reload_after, None);
+    }
+
+    #[test]
+    fn banner_geometries_are_capped() {
+        let clocks = registry();
+        let default = clocks.key(None).banner;
+
+        let banner = |width| Banner {
+            size: Size::new(width, 8),
+            scale: 1,
+        };
+
+        let _clocks: Vec<Clock> = (0..MAX_BANNER_GEOMETRIES as u16)
+            .map(|width| {
+                clocks.subscribe(
+                    clocks.banner_key(None, Editions::GifBanner, banner(10 + width)),
+                    &peer(),
+                    None,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            clocks
+                .banner_key(None, Editions::PngBanner, banner(10))
+                .banner,
+            banner(10)
+        );
+        assert_eq!(
+            clocks
+                .banner_key(None, Editions::PngBanner, banner(100))
+                .banner,
+            default
+        );
+    }
+}
@@ -1,44 +1,183 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::{
+    limits::{Admissions, Limits},
+    metrics,
+};
+
+/// The streaming endpoint a session is counted under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Edition {
+    Html,
+    Svg,
+    Select,
+    Rtl,
+    Dpu,
+    Gif,
+    Png,
+    Multipart,
+    Text,
+    Events,
+    Ws,
+}
+
+impl Edition {
+    pub const ALL: [Edition; 11] = [
+        Edition::Html,
+        Edition::Svg,
+        Edition::Select,
+        Edition::Rtl,
+        Edition::Dpu,
+        Edition::Gif,
+        Edition::Png,
+        Edition::Multipart,
+        Edition::Text,
+        Edition::Events,
+        Edition::Ws,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Edition::Html => "html",
+            Edition::Svg => "svg",
+            Edition::Select => "select",
+            Edition::Rtl => "rtl",
+            Edition::Dpu => "dpu",
+            Edition::Gif => "gif",
+            Edition::Png => "png",
+            Edition::Multipart => "multipart",
+            Edition::Text => "text",
+            Edition::Events => "events",
+            Edition::Ws => "ws",
+        }
+    }
+}
+
+/// Active sessions at one instant.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Breakdown {
+    pub total: usize,
+    pub editions: BTreeMap<Edition, usize>,
+    /// By IANA name.
+    pub zones: BTreeMap<&'static str, usize>,
+}
+
+impl Breakdown {
+    pub fn edition(&self, edition: Edition) -> usize {
+        self.editions.get(&edition).copied().unwrap_or_default()
+    }
+}
 
 #[derive(Clone)]
 pub struct ConnectionCounter {
-    count: Arc<AtomicUsize>,
+    counts: Arc<Mutex<HashMap<(Edition, Tz), usize>>>,
+    admissions: Arc<Admissions>,
 }
 
 pub struct Session<'a> {
     counter: &'a ConnectionCounter,
+    label: (Edition, Tz),
+    started: Instant,
 }
 
 impl ConnectionCounter {
-    pub fn new() -> Self {
+    pub fn new(limits: Limits) -> Self {
         ConnectionCounter {
-            count: Arc::new(AtomicUsize::from(0)),
+            counts: Arc::new(Mutex::new(HashMap::new())),
+            admissions: Arc::new(Admissions::new(limits)),
         }
     }
 
-    pub fn acquire(&self) -> Session<'_> {
-        self.count.fetch_add(1, Ordering::Relaxed);
+    pub fn admissions(&self) -> &Arc<Admissions> {
+        &self.admissions
+    }
+
+    pub fn acquire(&self, edition: Edition, tz: Tz) -> Session<'_> {
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .entry((edition, tz))
+            .or_default() += 1;
+
+        metrics::session_opened(edition);
         tracing::info!("Connection is established. conns: {}", self.current());
 
-        Session { counter: self }
+        Session {
+            counter: self,
+            label: (edition, tz),
+            started: Instant::now(),
+        }
     }
 
-    pub fn release(&self) {
-        self.count.fetch_sub(1, Ordering::Relaxed);
+    fn release(&self, label: (Edition, Tz)) {
+        let mut counts = self.counts.lock().unwrap();
+
+        if let Some(count) = counts.get_mut(&label) {
+            *count -= 1;
+
+            if *count == 0 {
+                counts.remove(&label);
+            }
+        }
     }
 
     pub fn current(&self) -> usize {
-        self.count.load(Ordering::Relaxed)
+        self.counts.lock().unwrap().values().sum()
+    }
+
+    pub fn breakdown(&self) -> Breakdown {
+        let mut breakdown = Breakdown::default();
+
+        for ((edition, tz), count) in self.counts.lock().unwrap().iter() {
+            breakdown.total += count;
+            *breakdown.editions.entry(*edition).or_default() += count;
+            *breakdown.zones.entry(tz.name()).or_default() += count;
+        }
+
+        breakdown
     }
 }
 
 impl Drop for Session<'_> {
     fn drop(&mut self) {
-        self.counter.release();
+        self.counter.release(self.label);
+        metrics::session_closed(self.label.0, self.started.elapsed());
         tracing::info!(
             "Connection has been closed. conns: {}",
             self.counter.current()
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_are_counted_per_edition_and_zone() {
+        let counter = ConnectionCounter::new(Limits::default());
+        let tokyo = chrono_tz::Asia::Tokyo;
+        let berlin = chrono_tz::Europe::Berlin;
+
+        let _html = counter.acquire(Edition::Html, tokyo);
+        let _gif = counter.acquire(Edition::Gif, tokyo);
+        let closed = counter.acquire(Edition::Gif, berlin);
+        let _berlin = counter.acquire(Edition::Html, berlin);
+        drop(closed);
+
+        let breakdown = counter.breakdown();
+        assert_eq!(breakdown.total, 3);
+        assert_eq!(breakdown.edition(Edition::Html), 2);
+        assert_eq!(breakdown.edition(Edition::Gif), 1);
+        assert_eq!(breakdown.edition(Edition::Svg), 0);
+        assert_eq!(breakdown.zones["Asia/Tokyo"], 2);
+        assert_eq!(breakdown.zones["Europe/Berlin"], 1);
+    }
+}
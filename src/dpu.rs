@@ -1,9 +1,16 @@
-use crate::{model::Context, Clock, ConnectionCounter};
+use crate::{
+    connection_counter::Edition,
+    limits::Admission,
+    metrics,
+    model::{render_head, ClockQuery, Context},
+    peer::Peer,
+    Clock, ClockRegistry, ConnectionCounter,
+};
 
 use async_stream::try_stream;
 use axum::{
     body::Body,
-    extract::State,
+    extract::{ConnectInfo, Query, State},
     http::{header, HeaderMap, HeaderName},
     response::IntoResponse,
 };
@@ -11,8 +18,26 @@ use bytes::Bytes;
 use futures::Stream;
 
 pub fn encode(ctx: &Context) -> Bytes {
-    let jst = ctx.jst.as_str();
-    let connection_count = ctx.connection_count;
+    let time = ctx.local_time();
+    let zone = ctx.zone_label();
+    let connection_count = ctx.connections.total;
+    let edition_count = ctx.connections.edition(Edition::Dpu);
+
+    if let Some(reload_after) = ctx.reload_after {
+        return bytes::Bytes::from(format!(
+            "\
+            <template for=\"clock\">\
+                <?start name=\"clock\">\
+                    <div>\
+                        <h2>Server restarting</h2>\
+                        <p>This page reloads in {reload_after} seconds.</p>\
+                        <meta http-equiv=refresh content={reload_after}>\
+                    </div>\
+                <?end>\
+            </template>
+        "
+        ));
+    }
 
     let user_emojis: String = if connection_count <= 50 {
         "👤".repeat(connection_count)
@@ -25,8 +50,8 @@ pub fn encode(ctx: &Context) -> Bytes {
             <template for=\"clock\">\
                 <?start name=\"clock\">\
                     <div>\
-                        <h2>{jst} <small>(JST)</small></h2>\
-                        <p>{connection_count} active connection(s).</p>\
+                        <h2>{time} <small>({zone})</small></h2>\
+                        <p>{edition_count} on this edition, {connection_count} total.</p>\
                         <p>{user_emojis}</p>\
                     </div>\
                 <?end>\
@@ -40,12 +65,15 @@ fn stream(
     counter: ConnectionCounter,
 ) -> impl Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Send + Sync>>> {
     try_stream! {
-        let _session = counter.acquire();
-        yield Bytes::from_static(include_bytes!("../assets/dpu.html"));
+        let _session = counter.acquire(Edition::Dpu, clock.key().tz);
+        let zone = clock.borrow().zone.clone();
+        yield render_head(include_str!("../assets/dpu.html"), &zone);
         clock.mark_unchanged();
 
         loop {
-            let _ = clock.changed().await;
+            if clock.changed().await.is_err() {
+                break;
+            }
             let partial = clock.borrow_and_update().dpu.clone();
             yield partial;
         }
@@ -54,10 +82,16 @@ fn stream(
 
 pub async fn handler(
     headers: HeaderMap,
-    State((clock, counter)): State<(Clock, ConnectionCounter)>,
+    Query(query): Query<ClockQuery>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    admission: Admission,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
 ) -> impl IntoResponse {
-    let stream = stream(clock, counter);
-    let body = Body::from_stream(stream);
+    let stream = stream(
+        clocks.subscribe(clocks.key(query.tz), &peer, admission.deadline()),
+        counter,
+    );
+    let body = Body::from_stream(metrics::count_bytes(Edition::Dpu, admission.limit(stream)));
 
     let is_cloudflare = headers.contains_key("cf-ray");
 
@@ -0,0 +1,196 @@
+use crate::{
+    connection_counter::Edition,
+    limits::Admission,
+    metrics,
+    model::{ClockQuery, Context},
+    peer::Peer,
+    Clock, ClockRegistry, ConnectionCounter,
+};
+
+use async_stream::try_stream;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap},
+    response::IntoResponse,
+};
+use bytes::Bytes;
+use chrono::SecondsFormat;
+use futures::Stream;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct Payload {
+    timestamp: i64,
+    iso8601: String,
+    zone: String,
+    connection_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reload_after: Option<u64>,
+}
+
+pub fn encode(ctx: &Context) -> Bytes {
+    let payload = Payload {
+        timestamp: ctx.timestamp,
+        iso8601: ctx
+            .utc
+            .with_timezone(&ctx.tz)
+            .to_rfc3339_opts(SecondsFormat::Millis, false),
+        zone: ctx.zone_label(),
+        connection_count: ctx.connections.total,
+        reload_after: ctx.reload_after,
+    };
+
+    Bytes::from(serde_json::to_vec(&payload).unwrap())
+}
+
+fn event(timestamp: i64, json: &[u8]) -> Bytes {
+    let mut event = format!("id: {timestamp}\ndata: ").into_bytes();
+    event.extend_from_slice(json);
+    event.extend_from_slice(b"\n\n");
+
+    Bytes::from(event)
+}
+
+fn stream(
+    mut clock: Clock,
+    counter: ConnectionCounter,
+    last_event_id: Option<i64>,
+) -> impl Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Send + Sync>>> {
+    try_stream! {
+        let _session = counter.acquire(Edition::Events, clock.key().tz);
+        yield Bytes::from_static(b"retry: 1000\n\n");
+
+        let (timestamp, current) = {
+            let data = clock.borrow_and_update();
+            (data.timestamp, event(data.timestamp, &data.json))
+        };
+
+        // A reconnecting client already has the event it names, anything else is behind.
+        if last_event_id != Some(timestamp) {
+            yield current;
+        }
+
+        loop {
+            if clock.changed().await.is_err() {
+                break;
+            }
+
+            let partial = {
+                let data = clock.borrow_and_update();
+                event(data.timestamp, &data.json)
+            };
+
+            yield partial;
+        }
+
+        // EventSource reconnects on its own, so it is only told to wait for the restart.
+        let reload_after = clock.borrow().reload_after;
+
+        if let Some(reload_after) = reload_after {
+            yield Bytes::from(format!("retry: {}\n\n", reload_after * 1000));
+        }
+    }
+}
+
+/// The current payload, for clients which don't want a stream.
+pub async fn snapshot_handler(
+    Query(query): Query<ClockQuery>,
+    State((clocks, _)): State<(ClockRegistry, ConnectionCounter)>,
+) -> impl IntoResponse {
+    let json = clocks.latest(clocks.key(query.tz)).json;
+
+    ([(header::CONTENT_TYPE, "application/json")], json)
+}
+
+pub async fn handler(
+    headers: HeaderMap,
+    Query(query): Query<ClockQuery>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    admission: Admission,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
+) -> impl IntoResponse {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+
+    let stream = stream(
+        clocks.subscribe(clocks.key(query.tz), &peer, admission.deadline()),
+        counter,
+        last_event_id,
+    );
+    let body = Body::from_stream(metrics::count_bytes(
+        Edition::Events,
+        admission.limit(stream),
+    ));
+
+    // No gRPC disguise: EventSource rejects other types, and Cloudflare doesn't buffer this one.
+    let headers = [
+        (header::CONTENT_TYPE, "text/event-stream"),
+        (header::CACHE_CONTROL, "no-cache"),
+    ];
+
+    (headers, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::clock_registry::tests::{peer, registry};
+
+    async fn next(
+        stream: &mut (impl Stream<Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>>
+                  + Unpin),
+    ) -> String {
+        String::from_utf8(stream.next().await.unwrap().unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn events_start_with_retry_and_are_identified_by_timestamp() {
+        let clocks = registry();
+        let counter = ConnectionCounter::new(Default::default());
+        let clock = clocks.subscribe(clocks.key(None), &peer(), None);
+        let timestamp = clock.borrow().timestamp;
+
+        let mut events = Box::pin(stream(clock, counter, Some(timestamp - 1000)));
+
+        assert_eq!(next(&mut events).await, "retry: 1000\n\n");
+
+        let event = next(&mut events).await;
+        let (id, data) = event.split_once('\n').unwrap();
+        assert_eq!(id, format!("id: {timestamp}"));
+
+        let data: serde_json::Value =
+            serde_json::from_str(data.strip_prefix("data: ").unwrap().trim_end()).unwrap();
+        assert_eq!(data["timestamp"], timestamp);
+        assert!(event.ends_with("\n\n"));
+    }
+
+    #[tokio::test]
+    async fn resumed_streams_skip_the_last_event_and_end_with_a_farewell() {
+        let clocks = registry();
+        let counter = ConnectionCounter::new(Default::default());
+        let clock = clocks.subscribe(clocks.key(None), &peer(), None);
+        let timestamp = clock.borrow().timestamp;
+
+        let mut events = Box::pin(stream(clock, counter, Some(timestamp)));
+        assert_eq!(next(&mut events).await, "retry: 1000\n\n");
+
+        // Farewells are identified by when they're sent, which must differ from the last tick.
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        clocks.close(Duration::from_secs(5));
+
+        // The client already has the event it resumed from, so the farewell comes next.
+        let farewell = next(&mut events).await;
+        assert!(!farewell.starts_with(&format!("id: {timestamp}\n")));
+        assert!(farewell.contains("\"reload_after\":5"), "{farewell}");
+
+        assert_eq!(next(&mut events).await, "retry: 5000\n\n");
+        assert!(events.next().await.is_none());
+    }
+}
@@ -0,0 +1,1728 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use once_cell::sync::OnceCell;
+
+use crate::mygif::Size;
+
+const GLYPH_COUNT: usize = 96;
+const FONT_SIZE: Size = Size::new(6, 10);
+
+static FONT: OnceCell<Font> = OnceCell::new();
+
+/// Printable ASCII from ' ' to '~', followed by the "tofu" glyph.
+static GLYPHS: [&[u8; (FONT_SIZE.width * FONT_SIZE.height) as usize]; GLYPH_COUNT] = [
+    // ' '
+    b"\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+",
+    // '!'
+    b"\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+______\
+__##__\
+__##__\
+",
+    // '"'
+    b"\
+##__##\
+##__##\
+##__##\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+",
+    // '#'
+    b"\
+______\
+_#__#_\
+_#__#_\
+######\
+_#__#_\
+_#__#_\
+######\
+_#__#_\
+_#__#_\
+______\
+",
+    // '$'
+    b"\
+__##__\
+_#####\
+##____\
+##____\
+_####_\
+____##\
+____##\
+#####_\
+__##__\
+______\
+",
+    // '%'
+    b"\
+______\
+##__##\
+##__##\
+___##_\
+__##__\
+__##__\
+_##___\
+##__##\
+##__##\
+______\
+",
+    // '&'
+    b"\
+_###__\
+##_##_\
+##_##_\
+_###__\
+####__\
+##_###\
+##__##\
+##__##\
+######\
+_###_#\
+",
+    // '\''
+    b"\
+__##__\
+__##__\
+__##__\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+",
+    // '('
+    b"\
+___##_\
+__##__\
+_##___\
+_##___\
+_##___\
+_##___\
+_##___\
+_##___\
+__##__\
+___##_\
+",
+    // ')'
+    b"\
+_##___\
+__##__\
+___##_\
+___##_\
+___##_\
+___##_\
+___##_\
+___##_\
+__##__\
+_##___\
+",
+    // '*'
+    b"\
+______\
+______\
+##__##\
+_####_\
+######\
+_####_\
+##__##\
+______\
+______\
+______\
+",
+    // '+'
+    b"\
+______\
+______\
+__##__\
+__##__\
+######\
+######\
+__##__\
+__##__\
+______\
+______\
+",
+    // ','
+    b"\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+__##__\
+__##__\
+_##___\
+",
+    // '-'
+    b"\
+______\
+______\
+______\
+______\
+######\
+######\
+______\
+______\
+______\
+______\
+",
+    // '.'
+    b"\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+__##__\
+__##__\
+",
+    // '/'
+    b"\
+____##\
+____##\
+___##_\
+___##_\
+__##__\
+__##__\
+_##___\
+_##___\
+##____\
+##____\
+",
+    // '0'
+    b"\
+_####_\
+######\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+######\
+_####_\
+",
+    // '1'
+    b"\
+___##_\
+_####_\
+_####_\
+___##_\
+___##_\
+___##_\
+___##_\
+___##_\
+___##_\
+___##_\
+",
+    // '2'
+    b"\
+_####_\
+######\
+____##\
+____##\
+_#####\
+_####_\
+##____\
+##____\
+######\
+######\
+",
+    // '3'
+    b"\
+#####_\
+######\
+____##\
+____##\
+######\
+######\
+____##\
+____##\
+######\
+#####_\
+",
+    // '4'
+    b"\
+##__##\
+##__##\
+##__##\
+##__##\
+######\
+######\
+____##\
+____##\
+____##\
+____##\
+",
+    // '5'
+    b"\
+######\
+######\
+##____\
+##____\
+#####_\
+_#####\
+____##\
+____##\
+######\
+#####_\
+",
+    // '6'
+    b"\
+_####_\
+######\
+##____\
+##____\
+#####_\
+######\
+##__##\
+##__##\
+######\
+_####_\
+",
+    // '7'
+    b"\
+######\
+######\
+##__##\
+##__##\
+____##\
+____##\
+____##\
+____##\
+____##\
+____##\
+",
+    // '8'
+    b"\
+_####_\
+######\
+##__##\
+##__##\
+######\
+######\
+##__##\
+##__##\
+######\
+_####_\
+",
+    // '9'
+    b"\
+_####_\
+######\
+##__##\
+##__##\
+######\
+_#####\
+____##\
+____##\
+######\
+_####_\
+",
+    // ':'
+    b"\
+______\
+______\
+__##__\
+__##__\
+______\
+______\
+______\
+__##__\
+__##__\
+______\
+",
+    // ';'
+    b"\
+______\
+______\
+__##__\
+__##__\
+______\
+______\
+______\
+__##__\
+__##__\
+_##___\
+",
+    // '<'
+    b"\
+______\
+____##\
+___##_\
+__##__\
+_##___\
+_##___\
+__##__\
+___##_\
+____##\
+______\
+",
+    // '='
+    b"\
+______\
+______\
+______\
+######\
+######\
+______\
+######\
+######\
+______\
+______\
+",
+    // '>'
+    b"\
+______\
+##____\
+_##___\
+__##__\
+___##_\
+___##_\
+__##__\
+_##___\
+##____\
+______\
+",
+    // '?'
+    b"\
+_####_\
+######\
+##__##\
+____##\
+___##_\
+__##__\
+__##__\
+______\
+__##__\
+__##__\
+",
+    // '@'
+    b"\
+_####_\
+##__##\
+#____#\
+#_####\
+#_#__#\
+#_#__#\
+#_####\
+#_____\
+##____\
+_#####\
+",
+    // 'A'
+    b"\
+_####_\
+######\
+##__##\
+##__##\
+######\
+######\
+##__##\
+##__##\
+##__##\
+##__##\
+",
+    // 'B'
+    b"\
+#####_\
+######\
+##__##\
+##__##\
+#####_\
+#####_\
+##__##\
+##__##\
+######\
+#####_\
+",
+    // 'C'
+    b"\
+_####_\
+######\
+##__##\
+##____\
+##____\
+##____\
+##____\
+##__##\
+######\
+_####_\
+",
+    // 'D'
+    b"\
+####__\
+#####_\
+##_###\
+##__##\
+##__##\
+##__##\
+##__##\
+##_###\
+#####_\
+####__\
+",
+    // 'E'
+    b"\
+######\
+######\
+##____\
+##____\
+#####_\
+#####_\
+##____\
+##____\
+######\
+######\
+",
+    // 'F'
+    b"\
+######\
+######\
+##____\
+##____\
+#####_\
+#####_\
+##____\
+##____\
+##____\
+##____\
+",
+    // 'G'
+    b"\
+_####_\
+######\
+##____\
+##____\
+##_###\
+##_###\
+##__##\
+##__##\
+######\
+_####_\
+",
+    // 'H'
+    b"\
+##__##\
+##__##\
+##__##\
+##__##\
+######\
+######\
+##__##\
+##__##\
+##__##\
+##__##\
+",
+    // 'I'
+    b"\
+######\
+######\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+######\
+######\
+",
+    // 'J'
+    b"\
+__####\
+__####\
+____##\
+____##\
+____##\
+____##\
+##__##\
+##__##\
+######\
+_####_\
+",
+    // 'K'
+    b"\
+##__##\
+##__##\
+##_##_\
+####__\
+###___\
+####__\
+##_##_\
+##__##\
+##__##\
+##__##\
+",
+    // 'L'
+    b"\
+##____\
+##____\
+##____\
+##____\
+##____\
+##____\
+##____\
+##____\
+######\
+######\
+",
+    // 'M'
+    b"\
+##__##\
+######\
+######\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+",
+    // 'N'
+    b"\
+##__##\
+###_##\
+###_##\
+######\
+######\
+##_###\
+##_###\
+##__##\
+##__##\
+##__##\
+",
+    // 'O'
+    b"\
+_####_\
+######\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+######\
+_####_\
+",
+    // 'P'
+    b"\
+#####_\
+######\
+##__##\
+##__##\
+######\
+#####_\
+##____\
+##____\
+##____\
+##____\
+",
+    // 'Q'
+    b"\
+_####_\
+######\
+##__##\
+##__##\
+##__##\
+##__##\
+##_###\
+##_###\
+######\
+_#####\
+",
+    // 'R'
+    b"\
+#####_\
+######\
+##__##\
+##__##\
+######\
+#####_\
+##_##_\
+##__##\
+##__##\
+##__##\
+",
+    // 'S'
+    b"\
+_####_\
+######\
+##____\
+##____\
+#####_\
+_#####\
+____##\
+____##\
+######\
+_####_\
+",
+    // 'T'
+    b"\
+######\
+######\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+",
+    // 'U'
+    b"\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+######\
+_####_\
+",
+    // 'V'
+    b"\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+_####_\
+_####_\
+__##__\
+",
+    // 'W'
+    b"\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+######\
+######\
+##__##\
+",
+    // 'X'
+    b"\
+##__##\
+##__##\
+_####_\
+_####_\
+__##__\
+__##__\
+_####_\
+_####_\
+##__##\
+##__##\
+",
+    // 'Y'
+    b"\
+##__##\
+##__##\
+##__##\
+_####_\
+_####_\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+",
+    // 'Z'
+    b"\
+######\
+######\
+____##\
+___##_\
+__##__\
+__##__\
+_##___\
+##____\
+######\
+######\
+",
+    // '['
+    b"\
+_####_\
+_####_\
+_##___\
+_##___\
+_##___\
+_##___\
+_##___\
+_##___\
+_####_\
+_####_\
+",
+    // '\\'
+    b"\
+##____\
+##____\
+_##___\
+_##___\
+__##__\
+__##__\
+___##_\
+___##_\
+____##\
+____##\
+",
+    // ']'
+    b"\
+_####_\
+_####_\
+___##_\
+___##_\
+___##_\
+___##_\
+___##_\
+___##_\
+_####_\
+_####_\
+",
+    // '^'
+    b"\
+__##__\
+_####_\
+##__##\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+",
+    // '_'
+    b"\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+######\
+######\
+",
+    // '`'
+    b"\
+_##___\
+__##__\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+______\
+",
+    // 'a'
+    b"\
+______\
+______\
+______\
+_####_\
+_#####\
+____##\
+_#####\
+##__##\
+######\
+_#####\
+",
+    // 'b'
+    b"\
+##____\
+##____\
+##____\
+#####_\
+######\
+##__##\
+##__##\
+##__##\
+######\
+#####_\
+",
+    // 'c'
+    b"\
+______\
+______\
+______\
+_####_\
+######\
+##____\
+##____\
+##____\
+######\
+_####_\
+",
+    // 'd'
+    b"\
+____##\
+____##\
+____##\
+_#####\
+######\
+##__##\
+##__##\
+##__##\
+######\
+_#####\
+",
+    // 'e'
+    b"\
+______\
+______\
+______\
+_####_\
+######\
+##__##\
+######\
+##____\
+######\
+_####_\
+",
+    // 'f'
+    b"\
+__###_\
+_####_\
+_##___\
+######\
+######\
+_##___\
+_##___\
+_##___\
+_##___\
+_##___\
+",
+    // 'g'
+    b"\
+______\
+______\
+______\
+_#####\
+##__##\
+##__##\
+######\
+_#####\
+____##\
+#####_\
+",
+    // 'h'
+    b"\
+##____\
+##____\
+##____\
+#####_\
+######\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+",
+    // 'i'
+    b"\
+__##__\
+__##__\
+______\
+_###__\
+_###__\
+__##__\
+__##__\
+__##__\
+_####_\
+_####_\
+",
+    // 'j'
+    b"\
+____##\
+____##\
+______\
+___###\
+____##\
+____##\
+____##\
+____##\
+##__##\
+_####_\
+",
+    // 'k'
+    b"\
+##____\
+##____\
+##____\
+##__##\
+##_##_\
+####__\
+###___\
+####__\
+##_##_\
+##__##\
+",
+    // 'l'
+    b"\
+_###__\
+_###__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+_####_\
+_####_\
+",
+    // 'm'
+    b"\
+______\
+______\
+______\
+##_##_\
+######\
+##_#_#\
+##_#_#\
+##_#_#\
+##_#_#\
+##_#_#\
+",
+    // 'n'
+    b"\
+______\
+______\
+______\
+#####_\
+######\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+",
+    // 'o'
+    b"\
+______\
+______\
+______\
+_####_\
+######\
+##__##\
+##__##\
+##__##\
+######\
+_####_\
+",
+    // 'p'
+    b"\
+______\
+______\
+______\
+#####_\
+##__##\
+##__##\
+######\
+#####_\
+##____\
+##____\
+",
+    // 'q'
+    b"\
+______\
+______\
+______\
+_#####\
+##__##\
+##__##\
+######\
+_#####\
+____##\
+____##\
+",
+    // 'r'
+    b"\
+______\
+______\
+______\
+##_###\
+######\
+###___\
+##____\
+##____\
+##____\
+##____\
+",
+    // 's'
+    b"\
+______\
+______\
+______\
+_#####\
+######\
+##____\
+######\
+____##\
+######\
+#####_\
+",
+    // 't'
+    b"\
+______\
+_##___\
+_##___\
+######\
+######\
+_##___\
+_##___\
+_##___\
+_#####\
+__####\
+",
+    // 'u'
+    b"\
+______\
+______\
+______\
+##__##\
+##__##\
+##__##\
+##__##\
+##__##\
+######\
+_#####\
+",
+    // 'v'
+    b"\
+______\
+______\
+______\
+##__##\
+##__##\
+##__##\
+##__##\
+_####_\
+_####_\
+__##__\
+",
+    // 'w'
+    b"\
+______\
+______\
+______\
+##_#_#\
+##_#_#\
+##_#_#\
+##_#_#\
+##_#_#\
+##_#_#\
+######\
+",
+    // 'x'
+    b"\
+______\
+______\
+______\
+##__##\
+##__##\
+_####_\
+__##__\
+_####_\
+##__##\
+##__##\
+",
+    // 'y'
+    b"\
+______\
+______\
+______\
+##__##\
+##__##\
+##__##\
+######\
+_#####\
+____##\
+#####_\
+",
+    // 'z'
+    b"\
+______\
+______\
+______\
+######\
+######\
+___##_\
+__##__\
+_##___\
+######\
+######\
+",
+    // '{'
+    b"\
+___###\
+__##__\
+__##__\
+__##__\
+###___\
+###___\
+__##__\
+__##__\
+__##__\
+___###\
+",
+    // '|'
+    b"\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+__##__\
+",
+    // '}'
+    b"\
+###___\
+__##__\
+__##__\
+__##__\
+___###\
+___###\
+__##__\
+__##__\
+__##__\
+###___\
+",
+    // '~'
+    b"\
+______\
+______\
+______\
+_##__#\
+######\
+#__##_\
+______\
+______\
+______\
+______\
+",
+    // tofu
+    b"\
+######\
+##__##\
+#____#\
+###__#\
+###__#\
+##___#\
+##__##\
+######\
+##__##\
+######\
+",
+];
+
+/// A monospaced bitmap font. Each glyph is a row-major bitmap of 0 (background) and 1 (ink).
+#[derive(Debug)]
+pub struct Font {
+    pub size: Size,
+    glyphs: Vec<Vec<u8>>,
+    index: HashMap<char, usize>,
+    fallback: usize,
+}
+
+/// Larger glyphs wouldn't fit a banner anyway, and BDF rows are read into a `u128`.
+const MAX_GLYPH_SIZE: i64 = 128;
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn glyph_size(width: i64, height: i64) -> io::Result<Size> {
+    if !(1..=MAX_GLYPH_SIZE).contains(&width) || !(1..=MAX_GLYPH_SIZE).contains(&height) {
+        return Err(invalid_data(format!(
+            "glyphs of {width}x{height} aren't between 1x1 and {MAX_GLYPH_SIZE}x{MAX_GLYPH_SIZE}"
+        )));
+    }
+
+    Ok(Size::new(width as u16, height as u16))
+}
+
+/// Outline of the whole cell, used when a loaded font has no default glyph.
+fn tofu(size: Size) -> Vec<u8> {
+    let (width, height) = (size.width as usize, size.height as usize);
+
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            (x == 0 || y == 0 || x == width - 1 || y == height - 1) as u8
+        })
+        .collect()
+}
+
+impl Font {
+    pub fn builtin() -> Self {
+        let glyphs = GLYPHS
+            .iter()
+            .map(|v| {
+                v.iter()
+                    .map(|c| match c {
+                        b'_' => 0u8,
+                        b'#' => 1u8,
+                        _ => 0u8,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let index = (' '..='~').enumerate().map(|(i, c)| (c, i)).collect();
+
+        Font {
+            size: FONT_SIZE,
+            glyphs,
+            index,
+            fallback: GLYPH_COUNT - 1,
+        }
+    }
+
+    /// Loads a BDF or PSF (version 1 or 2) font, telling them apart by their magic.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = std::fs::read(path)?;
+
+        match data.as_slice() {
+            [0x36, 0x04, ..] | [0x72, 0xB5, 0x4A, 0x86, ..] => Self::from_psf(&data),
+            _ => Self::from_bdf(std::str::from_utf8(&data).map_err(invalid_data_from)?),
+        }
+    }
+
+    /// Parses a monospaced BDF font. Glyphs are placed into the cell given by `FONTBOUNDINGBOX`.
+    pub fn from_bdf(text: &str) -> io::Result<Self> {
+        fn numbers<const N: usize>(args: &str) -> io::Result<[i64; N]> {
+            let numbers: Vec<i64> = args
+                .split_whitespace()
+                .map(|v| v.parse().map_err(invalid_data_from))
+                .collect::<io::Result<_>>()?;
+
+            numbers
+                .try_into()
+                .map_err(|_| invalid_data(format!("expected {N} numbers: {args:?}")))
+        }
+
+        let mut bounding_box: Option<[i64; 4]> = None;
+        let mut default_char: Option<u32> = None;
+        let mut encoding: Option<u32> = None;
+        let mut bbx = [0i64; 4];
+        let mut bitmap: Option<Vec<u128>> = None;
+
+        let mut glyphs = vec![];
+        let mut index = HashMap::new();
+        let mut default_glyph = None;
+
+        for line in text.lines() {
+            let (keyword, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+
+            if let Some(rows) = bitmap.as_mut() {
+                if keyword != "ENDCHAR" {
+                    rows.push(u128::from_str_radix(keyword, 16).map_err(invalid_data_from)?);
+                    continue;
+                }
+
+                let [width, height, x_offset, y_offset] =
+                    bounding_box.ok_or_else(|| invalid_data("FONTBOUNDINGBOX is missing"))?;
+
+                let mut glyph = vec![0u8; (width * height) as usize];
+
+                // Bits of each row are left aligned to a multiple of 8.
+                let row_bits = (bbx[0] + 7) / 8 * 8;
+                let out_of_range = || invalid_data(format!("BBX {bbx:?} is out of range"));
+                let left = bbx[2].checked_sub(x_offset).ok_or_else(out_of_range)?;
+                let top = height
+                    .checked_add(y_offset)
+                    .zip(bbx[1].checked_add(bbx[3]))
+                    .and_then(|(baseline, above)| baseline.checked_sub(above))
+                    .ok_or_else(out_of_range)?;
+
+                for (row, bits) in rows.iter().enumerate() {
+                    for column in 0..bbx[0] {
+                        let (x, y) = left
+                            .checked_add(column)
+                            .zip(top.checked_add(row as i64))
+                            .ok_or_else(out_of_range)?;
+
+                        if (bits >> (row_bits - 1 - column)) & 1 == 1
+                            && (0..width).contains(&x)
+                            && (0..height).contains(&y)
+                        {
+                            glyph[(y * width + x) as usize] = 1;
+                        }
+                    }
+                }
+
+                if let Some(c) = encoding.and_then(char::from_u32) {
+                    index.insert(c, glyphs.len());
+                }
+
+                if encoding.is_some() && encoding == default_char {
+                    default_glyph = Some(glyphs.len());
+                }
+
+                glyphs.push(glyph);
+                bitmap = None;
+                continue;
+            }
+
+            match keyword {
+                "FONTBOUNDINGBOX" => {
+                    let [width, height, x_offset, y_offset] = numbers(args)?;
+                    glyph_size(width, height)?;
+                    bounding_box = Some([width, height, x_offset, y_offset]);
+                }
+                "DEFAULT_CHAR" => default_char = Some(numbers::<1>(args)?[0] as u32),
+                "STARTCHAR" => encoding = None,
+                "ENCODING" => {
+                    encoding =
+                        u32::try_from(numbers::<1>(args.split(' ').next().unwrap_or(""))?[0]).ok()
+                }
+                "BBX" => {
+                    bbx = numbers(args)?;
+
+                    if !(0..=MAX_GLYPH_SIZE).contains(&bbx[0]) || bbx[1] < 0 {
+                        return Err(invalid_data(format!("BBX {args} is out of range")));
+                    }
+                }
+                "BITMAP" => bitmap = Some(vec![]),
+                _ => {}
+            }
+        }
+
+        let [width, height, ..] =
+            bounding_box.ok_or_else(|| invalid_data("FONTBOUNDINGBOX is missing"))?;
+
+        Ok(Self::with_fallback(
+            glyph_size(width, height)?,
+            glyphs,
+            index,
+            default_glyph,
+        ))
+    }
+
+    /// Parses a PSF1 or PSF2 console font, including its unicode table if present.
+    pub fn from_psf(data: &[u8]) -> io::Result<Self> {
+        let u32_at = |offset: usize| -> io::Result<u32> {
+            data.get(offset..offset + 4)
+                .map(|v| u32::from_le_bytes(v.try_into().unwrap()))
+                .ok_or_else(|| invalid_data("PSF header is truncated"))
+        };
+
+        // (header size, glyph count, bytes per glyph, height, width, has unicode table, is psf2)
+        let (header_size, count, glyph_bytes, height, width, has_table, is_psf2) = match data {
+            [0x36, 0x04, mode, charsize, ..] => (
+                4,
+                if mode & 0x01 != 0 { 512 } else { 256 },
+                *charsize as usize,
+                *charsize as usize,
+                8,
+                mode & 0x06 != 0,
+                false,
+            ),
+            [0x72, 0xB5, 0x4A, 0x86, ..] => (
+                u32_at(8)? as usize,
+                u32_at(16)? as usize,
+                u32_at(20)? as usize,
+                u32_at(24)? as usize,
+                u32_at(28)? as usize,
+                u32_at(12)? & 0x01 != 0,
+                true,
+            ),
+            _ => return Err(invalid_data("not a PSF font")),
+        };
+
+        let size = glyph_size(width as i64, height as i64)?;
+        let row_bytes = width.div_ceil(8);
+
+        if glyph_bytes < row_bytes * height {
+            return Err(invalid_data("PSF glyphs are smaller than their size"));
+        }
+
+        let bitmaps = count
+            .checked_mul(glyph_bytes)
+            .and_then(|v| v.checked_add(header_size))
+            .and_then(|table_offset| data.get(header_size..table_offset))
+            .ok_or_else(|| invalid_data("PSF glyphs are truncated"))?;
+        let table_offset = header_size + bitmaps.len();
+
+        let glyphs: Vec<Vec<u8>> = bitmaps
+            .chunks(glyph_bytes)
+            .map(|bitmap| {
+                (0..width * height)
+                    .map(|i| {
+                        let (x, y) = (i % width, i / width);
+                        (bitmap[y * row_bytes + x / 8] >> (7 - x % 8)) & 1
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut index = HashMap::new();
+
+        if has_table {
+            let table = &data[table_offset..];
+
+            if is_psf2 {
+                // UTF-8 sequences terminated by 0xFF, 0xFE starts combining sequences.
+                for (glyph, entry) in table.split(|b| *b == 0xFF).take(count).enumerate() {
+                    let single = entry.split(|b| *b == 0xFE).next().unwrap_or_default();
+
+                    for c in String::from_utf8_lossy(single).chars() {
+                        index.entry(c).or_insert(glyph);
+                    }
+                }
+            } else {
+                // UCS-2 values terminated by 0xFFFF, 0xFFFE starts combining sequences.
+                let values: Vec<u16> = table
+                    .as_chunks::<2>()
+                    .0
+                    .iter()
+                    .map(|v| u16::from_le_bytes(*v))
+                    .collect();
+
+                for (glyph, entry) in values.split(|v| *v == 0xFFFF).take(count).enumerate() {
+                    let single = entry.split(|v| *v == 0xFFFE).next().unwrap_or_default();
+
+                    for c in single.iter().filter_map(|v| char::from_u32(*v as u32)) {
+                        index.entry(c).or_insert(glyph);
+                    }
+                }
+            }
+        } else {
+            index = (0..count.min(256) as u32)
+                .filter_map(char::from_u32)
+                .enumerate()
+                .map(|(i, c)| (c, i))
+                .collect();
+        }
+
+        let fallback = index.get(&'?').copied();
+
+        Ok(Self::with_fallback(size, glyphs, index, fallback))
+    }
+
+    fn with_fallback(
+        size: Size,
+        mut glyphs: Vec<Vec<u8>>,
+        index: HashMap<char, usize>,
+        fallback: Option<usize>,
+    ) -> Self {
+        let fallback = fallback.unwrap_or_else(|| {
+            glyphs.push(tofu(size));
+            glyphs.len() - 1
+        });
+
+        Font {
+            size,
+            glyphs,
+            index,
+            fallback,
+        }
+    }
+
+    pub fn glyphs(&self) -> &[Vec<u8>] {
+        &self.glyphs
+    }
+
+    /// Index of the glyph for `c`, or of the fallback glyph if the font lacks it.
+    pub fn glyph_index(&self, c: char) -> usize {
+        self.index.get(&c).copied().unwrap_or(self.fallback)
+    }
+}
+
+fn invalid_data_from(error: impl std::error::Error) -> io::Error {
+    invalid_data(error.to_string())
+}
+
+pub fn get() -> &'static Font {
+    FONT.get().unwrap()
+}
+
+pub fn initialization(font: Font) {
+    FONT.set(font).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BDF: &str = "\
+STARTFONT 2.1
+FONTBOUNDINGBOX 4 4 0 0
+DEFAULT_CHAR 63
+CHARS 2
+STARTCHAR A
+ENCODING 65
+BBX 4 4 0 0
+BITMAP
+60
+90
+F0
+90
+ENDCHAR
+STARTCHAR question
+ENCODING 63
+BBX 2 2 1 1
+BITMAP
+C0
+40
+ENDCHAR
+ENDFONT
+";
+
+    fn psf2(width: u32, height: u32, glyphs: &[&[u8]], table: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x72, 0xB5, 0x4A, 0x86];
+
+        for v in [
+            0,
+            32,
+            1,
+            glyphs.len() as u32,
+            glyphs[0].len() as u32,
+            height,
+            width,
+        ] {
+            data.extend(v.to_le_bytes());
+        }
+
+        data.extend(glyphs.concat());
+        data.extend(table);
+        data
+    }
+
+    fn glyph(font: &Font, c: char) -> &[u8] {
+        &font.glyphs()[font.glyph_index(c)]
+    }
+
+    #[test]
+    fn bdf_places_glyphs_in_their_bounding_box() {
+        let font = Font::from_bdf(BDF).unwrap();
+
+        assert_eq!(font.size, Size::new(4, 4));
+        assert_eq!(
+            glyph(&font, 'A'),
+            [0, 1, 1, 0, 1, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0, 1]
+        );
+        assert_eq!(
+            glyph(&font, '?'),
+            [0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(font.glyph_index('Z'), font.glyph_index('?'));
+    }
+
+    #[test]
+    fn bdf_rejects_sizes_out_of_range() {
+        for (from, to) in [
+            ("FONTBOUNDINGBOX 4 4 0 0", "FONTBOUNDINGBOX -4 4 0 0"),
+            ("FONTBOUNDINGBOX 4 4 0 0", "FONTBOUNDINGBOX 0 4 0 0"),
+            ("BBX 4 4 0 0", "BBX 200 4 0 0"),
+            ("BBX 4 4 0 0", "BBX 4 -4 0 0"),
+            ("BBX 4 4 0 0", "BBX 4 9223372036854775807 0 1"),
+            ("BBX 4 4 0 0", "BBX 4 4 9223372036854775807 0"),
+            ("FONTBOUNDINGBOX 4 4 0 0", "FONTBOUNDINGBOX 4 4 0 9223372036854775807"),
+            ("FONTBOUNDINGBOX 4 4 0 0", "FONTBOUNDINGBOX 4 4 -9223372036854775808 0"),
+        ] {
+            let error = Font::from_bdf(&BDF.replace(from, to)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{to}");
+        }
+    }
+
+    #[test]
+    fn psf1_reads_its_unicode_table() {
+        let mut data = vec![0x36, 0x04, 0x02, 2];
+        data.extend([0x00, 0x00, 0x80, 0x01]);
+        data.extend(vec![0x00; 254 * 2]);
+
+        // '?' for the first glyph, 'A' and 'Б' for the second, nothing for the others.
+        let mut table = vec![0x003F, 0xFFFF, 0x0041, 0x0411, 0xFFFF];
+        table.extend([0xFFFF; 254]);
+        data.extend(table.iter().flat_map(|v: &u16| v.to_le_bytes()));
+
+        let font = Font::from_psf(&data).unwrap();
+
+        assert_eq!(font.size, Size::new(8, 2));
+        assert_eq!(font.glyph_index('A'), 1);
+        assert_eq!(font.glyph_index('Б'), 1);
+        assert_eq!(
+            glyph(&font, 'A'),
+            [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]
+        );
+        assert_eq!(font.glyph_index('Z'), font.glyph_index('?'));
+    }
+
+    #[test]
+    fn psf2_reads_its_unicode_table_and_falls_back_to_tofu() {
+        // 'x' for the first glyph, 'y' and 'z' with a combining sequence for the second.
+        let table = b"x\xFFyz\xFEe\xCC\x81\xFF";
+        let data = psf2(3, 3, &[&[0xA0, 0x40, 0xA0], &[0xE0, 0x00, 0xE0]], table);
+
+        let font = Font::from_psf(&data).unwrap();
+
+        assert_eq!(font.size, Size::new(3, 3));
+        assert_eq!(glyph(&font, 'x'), [1, 0, 1, 0, 1, 0, 1, 0, 1]);
+        assert_eq!(font.glyph_index('y'), 1);
+        assert_eq!(font.glyph_index('z'), 1);
+        assert_eq!(font.glyph_index('e'), font.glyph_index('?'));
+        assert_eq!(glyph(&font, '?'), [1, 1, 1, 1, 0, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn psf_rejects_empty_and_truncated_glyphs() {
+        for data in [
+            psf2(0, 0, &[&[0x00]], b""),
+            psf2(200, 1, &[&[0x00; 25]], b""),
+            psf2(8, 2, &[&[0x00, 0x00]], b"")[..33].to_vec(),
+            vec![0x36, 0x04, 0x00, 0],
+        ] {
+            let error = Font::from_psf(&data).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
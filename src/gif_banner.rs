@@ -1,509 +1,155 @@
+use std::collections::HashMap;
 use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::Mutex;
 
 use crate::{
-    model::Context,
+    connection_counter::Edition,
+    font,
+    layout::{changed_cells, upscale, Cell, Layout},
+    limits::Admission,
+    metrics,
+    model::{Banner, ClockKey, Context, Editions},
     mygif::{
-        Block, Color, Extension, Gif, GraphicControlExtensionPacked, GraphicsControlExtension,
-        HeaderPacked, Image, ImagePacked, ImagePositioned, Position, Size, Version,
+        Block, Color, CommentExtension, Extension, Gif, GraphicControlExtensionPacked,
+        GraphicsControlExtension, HeaderPacked, Image, ImagePacked, ImagePositioned, Position,
+        Size, Version, LZW_CODESIZE,
     },
-    Clock, ConnectionCounter,
+    peer::Peer,
+    Clock, ClockRegistry, ConnectionCounter,
 };
 
 use async_stream::try_stream;
 use axum::{
     body::Body,
-    extract::State,
+    extract::{ConnectInfo, Query, State},
     http::{header, HeaderMap, HeaderName},
     response::IntoResponse,
 };
 use binrw::BinWrite;
 use bytes::Bytes;
 use futures::Stream;
-use once_cell::sync::OnceCell;
-
-const GLYPH_COUNT: usize = 13;
-const FONT_SIZE: Size = Size::new(6, 10);
-
-static LZW_ENCODED_FONTS: OnceCell<[bytes::Bytes; GLYPH_COUNT]> = OnceCell::new();
-static LZW_ENCODED_BG: OnceCell<bytes::Bytes> = OnceCell::new();
-static GIF_HEADER: OnceCell<bytes::Bytes> = OnceCell::new();
-static GLYPHS: [&[u8; (FONT_SIZE.width * FONT_SIZE.height) as usize]; GLYPH_COUNT] = [
-    b"\
-_####_\
-######\
-##__##\
-##__##\
-##__##\
-##__##\
-##__##\
-##__##\
-######\
-_####_\
-",
-    b"\
-___##_\
-_####_\
-_####_\
-___##_\
-___##_\
-___##_\
-___##_\
-___##_\
-___##_\
-___##_\
-",
-    b"\
-_####_\
-######\
-____##\
-____##\
-_#####\
-_####_\
-##____\
-##____\
-######\
-######\
-",
-    b"\
-#####_\
-######\
-____##\
-____##\
-######\
-######\
-____##\
-____##\
-######\
-#####_\
-",
-    b"\
-##__##\
-##__##\
-##__##\
-##__##\
-######\
-######\
-____##\
-____##\
-____##\
-____##\
-",
-    b"\
-######\
-######\
-##____\
-##____\
-#####_\
-_#####\
-____##\
-____##\
-######\
-#####_\
-",
-    b"\
-_####_\
-######\
-##____\
-##____\
-#####_\
-######\
-##__##\
-##__##\
-######\
-_####_\
-",
-    b"\
-######\
-######\
-##__##\
-##__##\
-____##\
-____##\
-____##\
-____##\
-____##\
-____##\
-",
-    b"\
-_####_\
-######\
-##__##\
-##__##\
-######\
-######\
-##__##\
-##__##\
-######\
-_####_\
-",
-    b"\
-_####_\
-######\
-##__##\
-##__##\
-######\
-_#####\
-____##\
-____##\
-######\
-_####_\
-",
-    b"\
-______\
-______\
-______\
-______\
-######\
-######\
-______\
-______\
-______\
-______\
-",
-    b"\
-______\
-______\
-__##__\
-__##__\
-______\
-______\
-______\
-__##__\
-__##__\
-______\
-",
-    b"\
-######\
-##__##\
-#____#\
-###__#\
-###__#\
-##___#\
-##__##\
-######\
-##__##\
-######\
-",
-];
-
-fn to_codepoint(c: u8) -> usize {
-    match c {
-        b'0' => 0,
-        b'1' => 1,
-        b'2' => 2,
-        b'3' => 3,
-        b'4' => 4,
-        b'5' => 5,
-        b'6' => 6,
-        b'7' => 7,
-        b'8' => 8,
-        b'9' => 9,
-        b'-' => 10,
-        b':' => 11,
-        _ => 12,
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Deserialize;
+
+/// Number of foreground colors, lines cycle through them.
+pub const INK_COLORS: usize = 3;
+/// Largest banner after scaling, larger requests are shrunk to fit.
+pub const MAX_SCREEN_SIZE: Size = Size::new(512, 512);
+pub const MAX_SCALE: u16 = 4;
+const BG_CACHE_LIMIT: usize = 64;
+
+/// Glyphs encoded on first use, so that only the few which banners draw are encoded
+/// however many the font has.
+static LZW_ENCODED_GLYPHS: Lazy<Mutex<HashMap<GlyphKey, bytes::Bytes>>> =
+    Lazy::new(Default::default);
+static LZW_ENCODED_BG: Lazy<Mutex<HashMap<Size, bytes::Bytes>>> = Lazy::new(Default::default);
+static DEFAULT_PALETTE: OnceCell<Palette> = OnceCell::new();
+static COMMENT: OnceCell<String> = OnceCell::new();
+
+/// Comma separated foreground colors, one per line of text.
+#[derive(Debug, Clone)]
+pub struct Inks(pub Vec<Color>);
+
+impl FromStr for Inks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let colors = s
+            .split(',')
+            .map(Color::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if colors.len() > INK_COLORS {
+            return Err(format!(
+                "at most {INK_COLORS} foreground colors are supported"
+            ));
+        }
+
+        Ok(Inks(colors))
     }
 }
 
-#[allow(clippy::identity_op)]
-#[allow(clippy::erasing_op)]
-pub fn encode(ctx: &Context) -> bytes::Bytes {
-    let mut buffer = Cursor::new(vec![]);
+#[derive(Debug, Clone)]
+pub struct Palette {
+    pub bg: Color,
+    pub fg: Inks,
+}
 
-    let yea_a = ctx.jst.as_bytes()[0];
-    let yea_b = ctx.jst.as_bytes()[1];
-    let yea_c = ctx.jst.as_bytes()[2];
-    let yea_d = ctx.jst.as_bytes()[3];
-    let sep_ym = ctx.jst.as_bytes()[4];
-    let mon_h = ctx.jst.as_bytes()[5];
-    let mon_l = ctx.jst.as_bytes()[6];
-    let sep_md = ctx.jst.as_bytes()[7];
-    let day_h = ctx.jst.as_bytes()[8];
-    let day_l = ctx.jst.as_bytes()[9];
-    let _ = ctx.jst.as_bytes()[10];
-    let hou_h = ctx.jst.as_bytes()[11];
-    let hou_l = ctx.jst.as_bytes()[12];
-    let sep_hm = ctx.jst.as_bytes()[13];
-    let min_h = ctx.jst.as_bytes()[14];
-    let min_l = ctx.jst.as_bytes()[15];
-    let sep_ms = ctx.jst.as_bytes()[16];
-    let sec_h = ctx.jst.as_bytes()[17];
-    let sec_l = ctx.jst.as_bytes()[18];
-
-    let glyphs = &LZW_ENCODED_FONTS.get().unwrap();
-
-    let blocks = [
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(0, 0),
-            image: Image {
-                size: Size::new(88, 31),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: LZW_ENCODED_BG.get().unwrap().to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 + 7 * 7, 16),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(sec_l)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 + 7 * 6, 16),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(sec_h)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 + 7 * 5, 16),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(sep_ms)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 + 7 * 4, 16),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(min_l)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 + 7 * 3, 16),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(min_h)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 + 7 * 2, 16),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(sep_hm)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 + 7 * 1, 16),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(hou_l)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 + 7 * 0, 16),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(hou_h)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 * 0, 4),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(yea_a)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 * 1, 4),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(yea_b)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 * 2, 4),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(yea_c)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 * 3, 4),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(yea_d)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 * 4, 4),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(sep_ym)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 * 5, 4),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(mon_h)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 * 6, 4),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(mon_l)].to_vec(),
-            },
-        }),
-        Block::Extension(Extension::GraphicsControlExtension(
-            GraphicsControlExtension {
-                delay_time: 2,
-                transpalent_color_index: 0,
-                packed: GraphicControlExtensionPacked::new(),
-            },
-        )),
-        Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 * 7, 4),
-            image: Image {
-                size: Size::new(6, 10),
-                packed: ImagePacked::new(),
-                local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(sep_md)].to_vec(),
-            },
-        }),
+impl Palette {
+    /// Background at index 0, followed by the foreground color of each ink.
+    pub fn global_color_table(&self) -> Vec<Color> {
+        let mut table = vec![self.bg];
+        table.extend(self.fg.0.iter().cycle().take(INK_COLORS));
+        table
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BannerQuery {
+    tz: Option<chrono_tz::Tz>,
+    width: Option<u16>,
+    height: Option<u16>,
+    scale: Option<u16>,
+    #[serde(default, deserialize_with = "crate::model::from_str")]
+    fg: Option<Inks>,
+    #[serde(default, deserialize_with = "crate::model::from_str")]
+    bg: Option<Color>,
+    /// Opacity of the background, only the PNG banner supports it.
+    pub alpha: Option<u8>,
+}
+
+impl BannerQuery {
+    /// The requested geometry, clamped to what the server is willing to render.
+    pub fn key(&self, clocks: &ClockRegistry, editions: Editions) -> ClockKey {
+        let default = clocks.key(self.tz).banner;
+        let scale = self.scale.unwrap_or(default.scale).clamp(1, MAX_SCALE);
+
+        let banner = Banner {
+            size: Size::new(
+                self.width
+                    .unwrap_or(default.size.width)
+                    .clamp(1, MAX_SCREEN_SIZE.width / scale),
+                self.height
+                    .unwrap_or(default.size.height)
+                    .clamp(1, MAX_SCREEN_SIZE.height / scale),
+            ),
+            scale,
+        };
+
+        clocks.banner_key(self.tz, editions, banner)
+    }
+
+    pub fn palette(&self) -> Palette {
+        let default = DEFAULT_PALETTE.get().unwrap();
+
+        Palette {
+            bg: self.bg.unwrap_or(default.bg),
+            fg: self.fg.clone().unwrap_or_else(|| default.fg.clone()),
+        }
+    }
+}
+
+fn background(size: Size) -> bytes::Bytes {
+    let mut cache = LZW_ENCODED_BG.lock().unwrap();
+
+    if cache.len() >= BG_CACHE_LIMIT && !cache.contains_key(&size) {
+        cache.clear();
+    }
+
+    cache
+        .entry(size)
+        .or_insert_with(|| {
+            let pixels = vec![0x00; size.width as usize * size.height as usize];
+            bytes::Bytes::from(crate::mygif::do_lzw(&pixels))
+        })
+        .clone()
+}
+
+fn glyph_blocks(cell: Cell, scale: u16) -> [Block; 2] {
+    let size = font::get().size;
+
+    [
         Block::Extension(Extension::GraphicsControlExtension(
             GraphicsControlExtension {
                 delay_time: 2,
@@ -512,14 +158,66 @@ pub fn encode(ctx: &Context) -> bytes::Bytes {
             },
         )),
         Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 * 8, 4),
+            position: Position::new(cell.position.left * scale, cell.position.top * scale),
             image: Image {
-                size: Size::new(6, 10),
+                size: Size::new(size.width * scale, size.height * scale),
                 packed: ImagePacked::new(),
                 local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(day_h)].to_vec(),
+                lzw_minimum_code_size: LZW_CODESIZE,
+                lzw_binary: glyph(scale, cell.ink % INK_COLORS, cell.glyph).to_vec(),
             },
         }),
+    ]
+}
+
+/// The zone is dropped by the layout if it doesn't fit.
+pub fn banner_text(time: String, zone: String) -> String {
+    format!("{time} {zone}")
+}
+
+/// What the banner shows for `ctx`, the farewell on the last frame.
+pub fn current_text(ctx: &Context) -> String {
+    match ctx.reload_after {
+        Some(reload_after) => format!("Restarting, reload in {reload_after}s"),
+        None => banner_text(ctx.local_time(), ctx.zone_label()),
+    }
+}
+
+/// Redraws only the cells whose glyph changed since the previous tick.
+///
+/// Falls back to a keyframe when the text moved, e.g. because its length changed.
+pub fn encode(ctx: &Context) -> bytes::Bytes {
+    if ctx.reload_after.is_some() {
+        return encode_keyframe(ctx);
+    }
+
+    let text = banner_text(ctx.local_time(), ctx.zone_label());
+    let previous_text = banner_text(ctx.previous_local_time(), ctx.previous_zone_label());
+
+    let Some(changed) = changed_cells(font::get(), ctx.banner.size, &previous_text, &text) else {
+        return encode_keyframe(ctx);
+    };
+
+    let mut buffer = Cursor::new(vec![]);
+
+    let blocks: Vec<Block> = changed
+        .into_iter()
+        .flat_map(|cell| glyph_blocks(cell, ctx.banner.scale))
+        .collect();
+
+    blocks.write_le(&mut buffer).unwrap();
+
+    bytes::Bytes::from(buffer.into_inner())
+}
+
+/// Clears the screen and draws every cell.
+pub fn encode_keyframe(ctx: &Context) -> bytes::Bytes {
+    let mut buffer = Cursor::new(vec![]);
+
+    let text = current_text(ctx);
+    let layout = Layout::centered(font::get(), ctx.banner.size, &text);
+
+    let mut blocks = vec![
         Block::Extension(Extension::GraphicsControlExtension(
             GraphicsControlExtension {
                 delay_time: 2,
@@ -528,16 +226,23 @@ pub fn encode(ctx: &Context) -> bytes::Bytes {
             },
         )),
         Block::Image(ImagePositioned {
-            position: Position::new(9 + 7 * 9, 4),
+            position: Position::new(0, 0),
             image: Image {
-                size: Size::new(6, 10),
+                size: ctx.banner.screen_size(),
                 packed: ImagePacked::new(),
                 local_color_table: vec![],
-                lzw_binary: glyphs[to_codepoint(day_l)].to_vec(),
+                lzw_minimum_code_size: LZW_CODESIZE,
+                lzw_binary: background(ctx.banner.screen_size()).to_vec(),
             },
         }),
     ];
 
+    blocks.extend(
+        layout
+            .cells()
+            .flat_map(|cell| glyph_blocks(cell, ctx.banner.scale)),
+    );
+
     blocks.write_le(&mut buffer).unwrap();
 
     bytes::Bytes::from(buffer.into_inner())
@@ -546,17 +251,43 @@ pub fn encode(ctx: &Context) -> bytes::Bytes {
 fn stream(
     mut clock: Clock,
     counter: ConnectionCounter,
+    header: Bytes,
 ) -> impl Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Send + Sync>>> {
     try_stream! {
-        let _session = counter.acquire();
-        yield GIF_HEADER.get().unwrap().clone();
-        clock.mark_unchanged();
+        let _session = counter.acquire(Edition::Gif, clock.key().tz);
+        yield header;
+
+        let (keyframes, mut timestamp) = {
+            let data = clock.borrow_and_update();
+            (data.keyframes.clone(), data.timestamp)
+        };
+
+        yield keyframes.gif();
 
         loop {
-            let _ = clock.changed().await;
-            let partial = clock.borrow_and_update().gif.clone();
-            yield partial;
+            if clock.changed().await.is_err() {
+                break;
+            }
+
+            let partial = {
+                let data = clock.borrow_and_update();
+
+                // The delta only applies on top of the frame we sent last.
+                let partial = if data.previous_timestamp == timestamp {
+                    Ok(data.gif.clone())
+                } else {
+                    Err(data.keyframes.clone())
+                };
+
+                timestamp = data.timestamp;
+                partial
+            };
+
+            // Rendered after letting go of the frame, so that the ticker can publish meanwhile.
+            yield partial.unwrap_or_else(|keyframes| keyframes.gif());
         }
+
+        yield Bytes::from_static(&[0x3B]);
     }
 }
 
@@ -569,10 +300,20 @@ pub async fn banner_page_handler() -> impl IntoResponse {
 
 pub async fn gif_handler(
     headers: HeaderMap,
-    State((clock, counter)): State<(Clock, ConnectionCounter)>,
+    Query(query): Query<BannerQuery>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    admission: Admission,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
 ) -> impl IntoResponse {
-    let stream = stream(clock, counter);
-    let body = Body::from_stream(stream);
+    let key = query.key(&clocks, Editions::GifBanner);
+    let palette = query.palette();
+
+    let stream = stream(
+        clocks.subscribe(key, &peer, admission.deadline()),
+        counter,
+        header(key.banner.screen_size(), &palette),
+    );
+    let body = Body::from_stream(metrics::count_bytes(Edition::Gif, admission.limit(stream)));
 
     let is_cloudflare = headers.contains_key("cf-ray");
 
@@ -594,42 +335,44 @@ pub async fn gif_handler(
     (headers, body)
 }
 
-fn init_image() {
-    let data: [[u8; (FONT_SIZE.width * FONT_SIZE.height) as usize]; GLYPH_COUNT] = GLYPHS.map(|v| {
-        v.map(|c| match c {
-            b'_' => 0u8,
-            b'#' => 1u8,
-            _ => 0u8,
-        })
-    });
-
-    let data: [bytes::Bytes; GLYPH_COUNT] =
-        data.map(|raw| bytes::Bytes::from(crate::mygif::do_lzw(&raw)));
+/// Scale factor, foreground color index and glyph index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey(u16, usize, usize);
 
-    LZW_ENCODED_FONTS.set(data).unwrap();
-
-    let bg = bytes::Bytes::from(crate::mygif::do_lzw(&[0x00; 88 * 31]));
-    LZW_ENCODED_BG.set(bg).unwrap();
+fn glyph(scale: u16, ink: usize, index: usize) -> bytes::Bytes {
+    LZW_ENCODED_GLYPHS
+        .lock()
+        .unwrap()
+        .entry(GlyphKey(scale, ink, index))
+        .or_insert_with(|| {
+            let font = font::get();
+            let pixels: Vec<u8> = font.glyphs()[index]
+                .iter()
+                .map(|v| v * (1 + ink as u8))
+                .collect();
+            let pixels = upscale(&pixels, font.size, scale);
+            bytes::Bytes::from(crate::mygif::do_lzw(&pixels))
+        })
+        .clone()
 }
 
-fn init_header() {
+fn header(size: Size, palette: &Palette) -> Bytes {
     let mut buffer = Cursor::new(vec![]);
 
     let mygif = Gif {
         version: Version::GIF89a,
-        screen_width: 88,
-        screen_height: 31,
+        screen_width: size.width,
+        screen_height: size.height,
         packed: HeaderPacked::new()
             .with_global_color_table_flag(true)
             .with_color_resolution(7),
         background_color_index: 0x00,
         pixel_aspect_ratio: 0,
-        global_color_table: [
-            Color::from_rgb(0x00, 0x00, 0x00),
-            Color::from_rgb(0xFF, 0xFF, 0xFF),
-        ]
-        .into(),
+        global_color_table: palette.global_color_table(),
         blocks: [
+            Block::Extension(Extension::CommentExtension(CommentExtension {
+                comment: COMMENT.get().unwrap().as_bytes().to_vec(),
+            })),
             Block::Extension(Extension::GraphicsControlExtension(
                 GraphicsControlExtension {
                     delay_time: 2,
@@ -640,10 +383,11 @@ fn init_header() {
             Block::Image(ImagePositioned {
                 position: Position::new(0, 0),
                 image: Image {
-                    size: Size::new(88, 31),
+                    size,
                     packed: ImagePacked::new(),
                     local_color_table: vec![],
-                    lzw_binary: LZW_ENCODED_BG.get().unwrap().to_vec(),
+                    lzw_minimum_code_size: LZW_CODESIZE,
+                    lzw_binary: background(size).to_vec(),
                 },
             }),
         ]
@@ -652,12 +396,289 @@ fn init_header() {
 
     mygif.write(&mut buffer).unwrap();
 
-    GIF_HEADER
-        .set(bytes::Bytes::from(buffer.into_inner()))
-        .unwrap();
+    bytes::Bytes::from(buffer.into_inner())
+}
+
+pub fn initialization(palette: Palette, instance_name: Option<&str>) {
+    let version = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+
+    let comment = match instance_name {
+        Some(name) => format!("{version} ({name})"),
+        None => version.to_string(),
+    };
+
+    COMMENT.set(comment).unwrap();
+    DEFAULT_PALETTE.set(palette).unwrap();
 }
 
-pub fn initialization() {
-    init_image();
-    init_header();
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Once;
+
+    use binrw::BinRead;
+    use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+
+    use super::*;
+    use crate::model::Precision;
+
+    const SIZE: Size = Size::new(88, 31);
+
+    pub(crate) fn palette() -> Palette {
+        Palette {
+            bg: Color::from_rgb(0x00, 0x00, 0x80),
+            fg: Inks(vec![
+                Color::from_rgb(0xFF, 0x00, 0x00),
+                Color::from_rgb(0x00, 0xFF, 0x00),
+            ]),
+        }
+    }
+
+    pub(crate) fn setup() {
+        static INIT: Once = Once::new();
+
+        INIT.call_once(|| {
+            font::initialization(font::Font::builtin());
+            initialization(palette(), Some("test"));
+        });
+    }
+
+    pub(crate) fn context(utc: DateTime<Utc>, banner: Banner) -> Context {
+        Context {
+            previous_timestamp: (utc - TimeDelta::seconds(1)).timestamp_millis(),
+            timestamp: utc.timestamp_millis(),
+            connections: Default::default(),
+            utc,
+            tz: chrono_tz::UTC,
+            editions: Editions::GifBanner,
+            banner,
+            precision: Precision::Second,
+            time_format: "%Y-%m-%d %H:%M:%S".into(),
+            sync: None,
+            reload_after: None,
+            zone_label: None,
+        }
+    }
+
+    /// How the round-trip tests stream and decode a banner format.
+    pub(crate) struct BannerFormat {
+        pub header: fn(Banner) -> Vec<u8>,
+        /// Both take and advance the sequence number of formats which have one.
+        pub keyframe: fn(&Context, &mut u32) -> Vec<u8>,
+        pub delta: fn(&Context, &mut u32) -> Vec<u8>,
+        /// Paints every frame of the stream onto one canvas of the given size.
+        pub composite: fn(&[u8], Size) -> Vec<u8>,
+    }
+
+    const GIF: BannerFormat = BannerFormat {
+        header: |banner| header(banner.screen_size(), &palette()).to_vec(),
+        keyframe: |ctx, _| encode_keyframe(ctx).to_vec(),
+        delta: |ctx, _| encode(ctx).to_vec(),
+        composite: |stream, _| composite(stream).1,
+    };
+
+    /// Draws `lines` straight from the font, centered one above the other, as the decoded banner
+    /// should look.
+    ///
+    /// Deliberately doesn't use `Layout`, so that a mistake there can't cancel itself out.
+    pub(crate) fn expected(banner: Banner, lines: [&str; 2]) -> Vec<u8> {
+        let font = font::get();
+        let (glyph_width, glyph_height) = (font.size.width as usize, font.size.height as usize);
+        let (width, height) = (banner.size.width as usize, banner.size.height as usize);
+        let scale = banner.scale as usize;
+
+        // One pixel between characters, two between lines.
+        let top = (height - (2 * glyph_height + 2)) / 2;
+        let mut canvas = vec![0u8; width * scale * height * scale];
+
+        for (row, line) in lines.iter().enumerate() {
+            let left = (width - (line.len() * (glyph_width + 1) - 1)) / 2;
+            let top = top + row * (glyph_height + 2);
+
+            for (column, c) in line.chars().enumerate() {
+                let glyph = &font.glyphs()[font.glyph_index(c)];
+
+                for (i, v) in glyph.iter().enumerate() {
+                    let x = left + column * (glyph_width + 1) + i % glyph_width;
+                    let y = top + i / glyph_width;
+
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            canvas[(y * scale + dy) * width * scale + x * scale + dx] =
+                                v * (1 + row as u8);
+                        }
+                    }
+                }
+            }
+        }
+
+        canvas
+    }
+
+    /// Decodes the stream and paints all of its images onto one canvas.
+    fn composite(stream: &[u8]) -> (Gif, Vec<u8>) {
+        let mut data = stream.to_vec();
+        data.push(0x3B);
+
+        let gif = Gif::read(&mut Cursor::new(data)).unwrap();
+        let width = gif.screen_width as usize;
+        let mut canvas = vec![0u8; width * gif.screen_height as usize];
+
+        for block in &gif.blocks {
+            let Block::Image(ImagePositioned { position, image }) = block else {
+                continue;
+            };
+
+            let image_width = image.size.width as usize;
+
+            for (i, v) in image.pixels().unwrap().into_iter().enumerate() {
+                let x = position.left as usize + i % image_width;
+                let y = position.top as usize + i / image_width;
+                canvas[y * width + x] = v;
+            }
+        }
+
+        (gif, canvas)
+    }
+
+    #[test]
+    fn header_round_trip() {
+        setup();
+
+        let (gif, canvas) = composite(&header(SIZE, &palette()));
+
+        assert_eq!((gif.screen_width, gif.screen_height), (88, 31));
+        assert_eq!(
+            gif.global_color_table,
+            vec![
+                Color::from_rgb(0x00, 0x00, 0x80),
+                Color::from_rgb(0xFF, 0x00, 0x00),
+                Color::from_rgb(0x00, 0xFF, 0x00),
+                Color::from_rgb(0xFF, 0x00, 0x00),
+            ]
+        );
+        assert!(canvas.iter().all(|v| *v == 0));
+
+        let Block::Extension(Extension::CommentExtension(comment)) = &gif.blocks[0] else {
+            panic!("the first block must be the comment");
+        };
+
+        assert_eq!(
+            comment.comment,
+            format!("http-clock {} (test)", env!("CARGO_PKG_VERSION")).as_bytes()
+        );
+    }
+
+    #[test]
+    fn frames_spell_the_timestamp() {
+        setup();
+
+        let banners = [
+            Banner {
+                size: SIZE,
+                scale: 1,
+            },
+            Banner {
+                size: Size::new(120, 40),
+                scale: 3,
+            },
+        ];
+
+        for format in [GIF, crate::apng_banner::tests::APNG] {
+            for banner in banners {
+                let screen = banner.screen_size();
+                let mut sequence = 0;
+
+                // Crosses midnight, so the date line changes as well.
+                let start = Utc.with_ymd_and_hms(2026, 12, 31, 23, 59, 57).unwrap();
+
+                let lines = [
+                    ["2026-12-31", "23:59:57 UTC"],
+                    ["2026-12-31", "23:59:58 UTC"],
+                    ["2026-12-31", "23:59:59 UTC"],
+                    ["2027-01-01", "00:00:00 UTC"],
+                    ["2027-01-01", "00:00:01 UTC"],
+                ];
+
+                let mut stream = (format.header)(banner);
+
+                for (second, lines) in lines.into_iter().enumerate() {
+                    let ctx = context(start + TimeDelta::seconds(second as i64), banner);
+
+                    if second == 0 {
+                        stream.extend((format.keyframe)(&ctx, &mut sequence));
+                    } else {
+                        stream.extend((format.delta)(&ctx, &mut sequence));
+                    }
+
+                    assert_eq!(
+                        (format.composite)(&stream, screen),
+                        expected(banner, lines),
+                        "{lines:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn delta_redraws_the_zone_across_dst() {
+        setup();
+
+        let banner = Banner {
+            size: SIZE,
+            scale: 1,
+        };
+
+        // 01:59:59 EST is followed by 03:00:00 EDT, the zone keeps its width.
+        let start = Utc.with_ymd_and_hms(2027, 3, 14, 6, 59, 59).unwrap();
+        let first = Context {
+            tz: chrono_tz::America::New_York,
+            ..context(start, banner)
+        };
+        let second = Context {
+            tz: chrono_tz::America::New_York,
+            ..context(start + TimeDelta::seconds(1), banner)
+        };
+
+        assert_eq!(first.zone_label(), "EST");
+        assert_eq!(second.previous_zone_label(), "EST");
+        assert_eq!(second.zone_label(), "EDT");
+
+        let mut stream = header(SIZE, &palette()).to_vec();
+        stream.extend(encode_keyframe(&first));
+        stream.extend(encode(&second));
+
+        assert_eq!(
+            composite(&stream).1,
+            expected(banner, ["2027-03-14", "03:00:00 EDT"])
+        );
+    }
+
+    #[test]
+    fn delta_only_redraws_changed_glyphs() {
+        setup();
+
+        let banner = Banner {
+            size: SIZE,
+            scale: 1,
+        };
+
+        let ctx = context(
+            Utc.with_ymd_and_hms(2026, 10, 17, 12, 34, 56).unwrap(),
+            banner,
+        );
+
+        let mut stream = header(SIZE, &palette()).to_vec();
+        stream.extend(encode(&ctx));
+
+        let (gif, _) = composite(&stream);
+        let images = gif
+            .blocks
+            .iter()
+            .filter(|block| matches!(block, Block::Image(_)))
+            .count();
+
+        // The background of the header and the last digit of the seconds.
+        assert_eq!(images, 2);
+    }
 }
@@ -1,9 +1,16 @@
-use crate::{model::Context, Clock, ConnectionCounter};
+use crate::{
+    connection_counter::Edition,
+    limits::Admission,
+    metrics,
+    model::{render_head, ClockKey, ClockQuery, Context},
+    peer::Peer,
+    Clock, ClockRegistry, ConnectionCounter,
+};
 
 use async_stream::try_stream;
 use axum::{
     body::Body,
-    extract::State,
+    extract::{ConnectInfo, Query, State},
     http::{header, HeaderMap, HeaderName},
     response::IntoResponse,
 };
@@ -11,11 +18,31 @@ use bytes::Bytes;
 use futures::Stream;
 
 pub fn encode(ctx: &Context) -> Bytes {
-    let jst = ctx.jst.as_str();
+    let time = ctx.local_time();
+    let zone = ctx.zone_label();
     let timestamp = ctx.timestamp;
-    let connection_count = ctx.connection_count;
+    let connection_count = ctx.connections.total;
+    let edition_count = ctx.connections.edition(Edition::Html);
     let previous_timestamp = ctx.previous_timestamp;
 
+    if let Some(reload_after) = ctx.reload_after {
+        return bytes::Bytes::from(format!(
+            "
+            <style>#e{previous_timestamp} {{ display: none; }}</style>\
+            <div>\
+                <h2>Server restarting</h2>\
+                <p>This page reloads in {reload_after} seconds.</p>\
+                <meta http-equiv=refresh content={reload_after}>\
+            </div>\
+        "
+        ));
+    }
+
+    let sync = match ctx.sync_note() {
+        Some(note) => format!("<p><small>{note}.</small></p>"),
+        None => String::new(),
+    };
+
     let user_emojis: String = if connection_count <= 50 {
         "👤".repeat(connection_count)
     } else {
@@ -26,9 +53,10 @@ pub fn encode(ctx: &Context) -> Bytes {
         "
             <style>#e{previous_timestamp} {{ display: none; }}</style>\
             <div id=e{timestamp}>\
-                <h2>{jst} <small>(JST)</small></h2>\
-                <p>{connection_count} active connection(s).</p>\
+                <h2>{time} <small>({zone})</small></h2>\
+                <p>{edition_count} on this edition, {connection_count} total.</p>\
                 <p>{user_emojis}</p>\
+                {sync}\
             </div>\
         "
     ))
@@ -39,12 +67,15 @@ fn stream(
     counter: ConnectionCounter,
 ) -> impl Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Send + Sync>>> {
     try_stream! {
-        let _session = counter.acquire();
-        yield Bytes::from_static(include_bytes!("../assets/head.html"));
+        let _session = counter.acquire(Edition::Html, clock.key().tz);
+        let zone = clock.borrow().zone.clone();
+        yield render_head(include_str!("../assets/head.html"), &zone);
         clock.mark_unchanged();
 
         loop {
-            let _ = clock.changed().await;
+            if clock.changed().await.is_err() {
+                break;
+            }
             let partial = clock.borrow_and_update().html.clone();
             yield partial;
         }
@@ -53,10 +84,18 @@ fn stream(
 
 pub async fn handler(
     headers: HeaderMap,
-    State((clock, counter)): State<(Clock, ConnectionCounter)>,
+    Query(query): Query<ClockQuery>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    admission: Admission,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
 ) -> impl IntoResponse {
-    let stream = stream(clock, counter);
-    let body = Body::from_stream(stream);
+    let key = ClockKey {
+        precision: query.precision.unwrap_or_default(),
+        ..clocks.key(query.tz)
+    };
+
+    let stream = stream(clocks.subscribe(key, &peer, admission.deadline()), counter);
+    let body = Body::from_stream(metrics::count_bytes(Edition::Html, admission.limit(stream)));
 
     let is_cloudflare = headers.contains_key("cf-ray");
 
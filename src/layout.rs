@@ -0,0 +1,175 @@
+use crate::{
+    font::Font,
+    mygif::{Position, Size},
+};
+
+/// Vertical gap between two lines of `Layout::centered`.
+pub const LINE_GAP: u16 = 2;
+
+/// A glyph placed on the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub position: Position,
+    pub glyph: usize,
+    pub ink: usize,
+}
+
+/// A row of text drawn from `origin` (top-left), moving `advance` pixels per character.
+///
+/// `ink` selects the foreground color; how it maps to a color is up to the renderer.
+#[derive(Debug, Clone)]
+pub struct TextLine<'a> {
+    pub font: &'a Font,
+    pub origin: Position,
+    pub advance: u16,
+    pub ink: usize,
+    pub text: String,
+}
+
+impl TextLine<'_> {
+    pub fn cells(&self) -> impl Iterator<Item = Cell> + '_ {
+        self.text
+            .chars()
+            .enumerate()
+            .filter(|(_, c)| !c.is_whitespace())
+            .map(|(column, c)| Cell {
+                position: Position::new(
+                    self.origin.left + column as u16 * self.advance,
+                    self.origin.top,
+                ),
+                glyph: self.font.glyph_index(c),
+                ink: self.ink,
+            })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Layout<'a> {
+    pub lines: Vec<TextLine<'a>>,
+}
+
+impl<'a> Layout<'a> {
+    /// Breaks `text` at whitespace into as many lines as fit into `screen` and centers them.
+    ///
+    /// Each line gets its row number as `ink`.
+    pub fn centered(font: &'a Font, screen: Size, text: &str) -> Self {
+        let advance = font.size.width + 1;
+        let line_height = font.size.height + LINE_GAP;
+
+        let columns = ((screen.width + 1) / advance) as usize;
+        let rows = ((screen.height + LINE_GAP) / line_height) as usize;
+
+        let mut lines: Vec<String> = vec![];
+
+        for word in text.split_whitespace() {
+            match lines.last_mut() {
+                Some(line) if line.chars().count() + 1 + word.chars().count() <= columns => {
+                    line.push(' ');
+                    line.push_str(word);
+                }
+                _ => lines.push(word.to_string()),
+            }
+        }
+
+        lines.truncate(rows);
+
+        let height = (lines.len() as u16 * line_height).saturating_sub(LINE_GAP);
+        let top = (screen.height - height) / 2;
+
+        let lines = lines
+            .into_iter()
+            .enumerate()
+            .map(|(row, line)| {
+                let text: String = line.chars().take(columns).collect();
+                let width = (text.chars().count() as u16 * advance).saturating_sub(1);
+
+                TextLine {
+                    font,
+                    origin: Position::new(
+                        (screen.width - width) / 2,
+                        top + row as u16 * line_height,
+                    ),
+                    advance,
+                    ink: row,
+                    text,
+                }
+            })
+            .collect();
+
+        Layout { lines }
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = Cell> + '_ {
+        self.lines.iter().flat_map(|line| line.cells())
+    }
+
+    /// Rasterizes into a row-major bitmap of `screen`, set pixels become `color(ink)`.
+    pub fn paint(&self, screen: Size, color: impl Fn(usize) -> u8) -> Vec<u8> {
+        let width = screen.width as usize;
+        let mut canvas = vec![0u8; width * screen.height as usize];
+
+        for line in &self.lines {
+            let glyph_width = line.font.size.width as usize;
+
+            for cell in line.cells() {
+                for (i, v) in line.font.glyphs()[cell.glyph].iter().enumerate() {
+                    let x = cell.position.left as usize + i % glyph_width;
+                    let y = cell.position.top as usize + i / glyph_width;
+                    canvas[y * width + x] = v * color(cell.ink);
+                }
+            }
+        }
+
+        canvas
+    }
+}
+
+/// The cells of `current` whose glyph differs from `previous`, both laid out by `Layout::centered`.
+///
+/// `None` if any cell moved, e.g. because the length of the text changed, so that only redrawing
+/// everything will do.
+pub fn changed_cells(
+    font: &Font,
+    screen: Size,
+    previous: &str,
+    current: &str,
+) -> Option<Vec<Cell>> {
+    let cells: Vec<Cell> = Layout::centered(font, screen, current).cells().collect();
+    let previous: Vec<Cell> = Layout::centered(font, screen, previous).cells().collect();
+
+    let is_moved = cells.len() != previous.len()
+        || cells
+            .iter()
+            .zip(&previous)
+            .any(|(cell, previous)| cell.position != previous.position);
+
+    if is_moved {
+        return None;
+    }
+
+    Some(
+        cells
+            .into_iter()
+            .zip(previous)
+            .filter(|(cell, previous)| cell.glyph != previous.glyph)
+            .map(|(cell, _)| cell)
+            .collect(),
+    )
+}
+
+/// Enlarges a row-major bitmap of `size` so that each pixel becomes `scale`x`scale`.
+pub fn upscale(pixels: &[u8], size: Size, scale: u16) -> Vec<u8> {
+    let (width, scale) = (size.width as usize, scale as usize);
+
+    pixels
+        .chunks(width)
+        .flat_map(|row| {
+            let row: Vec<u8> = row
+                .iter()
+                .flat_map(|v| std::iter::repeat_n(*v, scale))
+                .collect();
+
+            std::iter::repeat_n(row, scale).flatten()
+        })
+        .collect()
+}
@@ -0,0 +1,395 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_stream::stream;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::Stream;
+use tokio::time::Instant;
+
+use crate::{peer::Peer, ClockRegistry, ConnectionCounter};
+
+/// How long a rejected client is asked to wait before trying again.
+const RETRY_AFTER: &str = "60";
+
+/// An IP network like `10.0.0.0/8`, or a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let (network, addr, bits) = match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => (
+                u128::from(network.to_bits()),
+                u128::from(addr.to_bits()),
+                32,
+            ),
+            (IpAddr::V6(network), IpAddr::V6(addr)) => (network.to_bits(), addr.to_bits(), 128),
+            _ => return false,
+        };
+
+        let host_bits = bits - u32::from(self.prefix);
+        network.checked_shr(host_bits).unwrap_or(0) == addr.checked_shr(host_bits).unwrap_or(0)
+    }
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("{s:?} is not an IP address or network");
+
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr.parse().map_err(|_| error())?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|v| *v <= bits)
+                .ok_or_else(error)?,
+            None => bits,
+        };
+
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub max_streams: Option<usize>,
+    pub max_streams_per_client: Option<usize>,
+    pub max_lifetime: Option<Duration>,
+    /// Proxies whose `CF-Connecting-IP` and `X-Forwarded-For` are believed.
+    pub trusted_proxies: Vec<Cidr>,
+}
+
+impl Limits {
+    fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|cidr| cidr.contains(addr))
+    }
+
+    /// The address of whoever is behind the chain of trusted proxies that `addr` starts.
+    pub fn client(&self, addr: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(addr) {
+            return addr;
+        }
+
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+
+        if let Some(client) = header("cf-connecting-ip").and_then(|v| v.trim().parse().ok()) {
+            return client;
+        }
+
+        // Each proxy appends whom it got the request from, so the first untrusted one from the
+        // right is the client. Anything left of it may be made up.
+        let mut client = addr;
+
+        for forwarded in header("x-forwarded-for").unwrap_or_default().rsplit(',') {
+            let Ok(forwarded) = forwarded.trim().parse() else {
+                break;
+            };
+
+            client = forwarded;
+
+            if !self.is_trusted(forwarded) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+/// What `--max-streams-per-client` counts by: the address, or for IPv6 the /64 a host usually
+/// has to itself.
+fn bucket(client: IpAddr) -> IpAddr {
+    match client.to_canonical() {
+        IpAddr::V6(addr) => IpAddr::V6(Ipv6Addr::from_bits(addr.to_bits() & (!0 << 64))),
+        addr => addr,
+    }
+}
+
+#[derive(Debug, Default)]
+struct Open {
+    total: usize,
+    per_client: HashMap<IpAddr, usize>,
+}
+
+/// Streams admitted so far, shared by every handler through `ConnectionCounter`.
+#[derive(Debug)]
+pub struct Admissions {
+    limits: Limits,
+    open: Mutex<Open>,
+}
+
+impl Admissions {
+    pub fn new(limits: Limits) -> Self {
+        Admissions {
+            limits,
+            open: Mutex::default(),
+        }
+    }
+
+    fn admit(self: &Arc<Self>, client: IpAddr) -> Result<Admission, Rejection> {
+        let client = bucket(client);
+        let mut open = self.open.lock().unwrap();
+
+        if self.limits.max_streams.is_some_and(|max| open.total >= max) {
+            return Err(Rejection::Busy);
+        }
+
+        let per_client = open.per_client.entry(client).or_default();
+
+        if self
+            .limits
+            .max_streams_per_client
+            .is_some_and(|max| *per_client >= max)
+        {
+            return Err(Rejection::TooManyFromClient);
+        }
+
+        *per_client += 1;
+        open.total += 1;
+
+        Ok(Admission {
+            admissions: self.clone(),
+            client,
+            deadline: self.limits.max_lifetime.map(|v| Instant::now() + v),
+        })
+    }
+
+    fn release(&self, client: IpAddr) {
+        let mut open = self.open.lock().unwrap();
+        open.total -= 1;
+
+        if let Some(count) = open.per_client.get_mut(&client) {
+            *count -= 1;
+
+            if *count == 0 {
+                open.per_client.remove(&client);
+            }
+        }
+    }
+}
+
+/// A slot for one stream, taken when the request is extracted and given back when it ends.
+pub struct Admission {
+    admissions: Arc<Admissions>,
+    client: IpAddr,
+    deadline: Option<Instant>,
+}
+
+impl Admission {
+    /// When the stream has to end, never without `--max-session-lifetime`.
+    ///
+    /// Goes to `ClockRegistry::subscribe`, so that the stream still ends with a farewell frame.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Keeps the slot for as long as `inner` runs.
+    pub fn limit<S>(self, inner: S) -> impl Stream<Item = S::Item>
+    where
+        S: Stream,
+    {
+        stream! {
+            let _admission = self;
+
+            for await item in inner {
+                yield item;
+            }
+        }
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.admissions.release(self.client);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    Busy,
+    TooManyFromClient,
+    /// The router was served without `ConnectInfo<Peer>`, a bug rather than a busy server.
+    MissingConnectInfo,
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            Rejection::MissingConnectInfo => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Missing request extension: ConnectInfo<Peer>",
+                )
+                    .into_response()
+            }
+            Rejection::Busy => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "So many people are watching the clock right now that there's no room for you.",
+            ),
+            Rejection::TooManyFromClient => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "You already have as many clocks open as you may.",
+            ),
+        };
+
+        let page = include_str!("../assets/busy.html").replace("{message}", message);
+
+        (
+            status,
+            [
+                (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+                (header::RETRY_AFTER, RETRY_AFTER),
+            ],
+            page,
+        )
+            .into_response()
+    }
+}
+
+impl FromRequestParts<(ClockRegistry, ConnectionCounter)> for Admission {
+    type Rejection = Rejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        (_, counter): &(ClockRegistry, ConnectionCounter),
+    ) -> Result<Self, Self::Rejection> {
+        let admissions = counter.admissions();
+
+        let client = match parts.extensions.get::<ConnectInfo<Peer>>() {
+            Some(ConnectInfo(peer)) => admissions.limits.client(peer.addr.ip(), &parts.headers),
+            None => return Err(Rejection::MissingConnectInfo),
+        };
+
+        admissions.admit(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn limits(trusted_proxies: &[&str]) -> Limits {
+        Limits {
+            max_streams: Some(3),
+            max_streams_per_client: Some(2),
+            trusted_proxies: trusted_proxies.iter().map(|v| v.parse().unwrap()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_matches_its_prefix() {
+        let network: Cidr = "192.168.0.0/16".parse().unwrap();
+        assert!(network.contains(ip("192.168.12.34")));
+        assert!(network.contains(ip("::ffff:192.168.12.34")));
+        assert!(!network.contains(ip("192.169.0.1")));
+
+        let network: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(network.contains(ip("2001:db8::1")));
+        assert!(!network.contains(ip("2001:db9::1")));
+
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("8.8.8.8")));
+        assert!("10.0.0.1/33".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn forwarded_headers_only_count_from_trusted_proxies() {
+        let limits = limits(&["10.0.0.0/8"]);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("1.1.1.1, 203.0.113.7, 10.0.0.2"),
+        );
+
+        assert_eq!(
+            limits.client(ip("198.51.100.1"), &headers),
+            ip("198.51.100.1")
+        );
+        assert_eq!(limits.client(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+
+        headers.insert("cf-connecting-ip", HeaderValue::from_static("192.0.2.5"));
+        assert_eq!(limits.client(ip("10.0.0.1"), &headers), ip("192.0.2.5"));
+    }
+
+    #[test]
+    fn admissions_are_limited_per_client_and_in_total() {
+        let admissions = Arc::new(Admissions::new(limits(&[])));
+
+        let first = admissions.admit(ip("192.0.2.1")).unwrap();
+        let _second = admissions.admit(ip("192.0.2.1")).unwrap();
+        assert_eq!(
+            admissions.admit(ip("192.0.2.1")).err(),
+            Some(Rejection::TooManyFromClient)
+        );
+
+        let _third = admissions.admit(ip("192.0.2.2")).unwrap();
+        assert_eq!(
+            admissions.admit(ip("192.0.2.3")).err(),
+            Some(Rejection::Busy)
+        );
+
+        drop(first);
+        assert!(admissions.admit(ip("192.0.2.1")).is_ok());
+    }
+
+    #[test]
+    fn ipv6_clients_are_counted_by_their_64() {
+        let admissions = Arc::new(Admissions::new(limits(&[])));
+
+        let _first = admissions.admit(ip("2001:db8:0:1::1")).unwrap();
+        let _second = admissions.admit(ip("2001:db8:0:1:ffff::2")).unwrap();
+        assert_eq!(
+            admissions.admit(ip("2001:db8:0:1::3")).err(),
+            Some(Rejection::TooManyFromClient)
+        );
+
+        assert!(admissions.admit(ip("2001:db8:0:2::1")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn missing_connect_info_is_a_server_error() {
+        let state = (
+            crate::clock_registry::tests::registry(),
+            ConnectionCounter::new(limits(&[])),
+        );
+        let (mut parts, _) = axum::http::Request::new(()).into_parts();
+
+        let rejection = Admission::from_request_parts(&mut parts, &state)
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(rejection, Rejection::MissingConnectInfo);
+        assert_eq!(
+            rejection.into_response().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -1,106 +1,360 @@
-use std::net::SocketAddr;
+use std::{future::IntoFuture, net::SocketAddr, path::PathBuf, sync::Arc};
 
-use axum::{routing::get, Router};
-use chrono::{DateTime, Utc};
-use chrono_tz::Japan;
-use clap::Parser;
-use tokio::{net::TcpListener, sync::watch};
+use axum::{
+    routing::{any, get},
+    Router,
+};
+use chrono_tz::Tz;
+use clap::{CommandFactory, Parser};
+use futures::FutureExt;
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    sync::Notify,
+};
 
+mod api;
+mod apng_banner;
+mod clock_registry;
 mod connection_counter;
+mod events;
+mod font;
 mod gif_banner;
 mod html;
+mod layout;
+mod limits;
+mod metrics;
 mod model;
+mod multipart_banner;
 mod mygif;
+mod negotiation;
+mod ntp;
+mod ntp_server;
+mod peer;
+mod mypng;
 mod select;
 mod svg;
 mod rtl;
+mod text;
+mod time_source;
+mod tls;
 mod dpu;
+mod ws;
 
+use clock_registry::{Clock, ClockRegistry};
 use connection_counter::ConnectionCounter;
 
-type Clock = watch::Receiver<model::ClockData>;
-
 #[derive(Debug, Parser)]
 struct Cli {
     #[clap(long, env)]
     #[clap(default_value = "0.0.0.0:3000")]
     listen: SocketAddr,
+
+    /// IANA timezone used when a request doesn't specify `tz`.
+    #[clap(long, env)]
+    #[clap(default_value = "Asia/Tokyo")]
+    timezone: Tz,
+
+    /// strftime format of the displayed time.
+    #[clap(long, env, value_parser = parse_time_format)]
+    #[clap(default_value = "%Y-%m-%d %H:%M:%S")]
+    time_format: String,
+
+    /// Label shown instead of the abbreviation of the default timezone.
+    #[clap(long, env)]
+    zone_label: Option<String>,
+
+    /// Default size of the GIF banner.
+    #[clap(long, env, value_parser = clap::value_parser!(u16).range(1..=512))]
+    #[clap(default_value = "88")]
+    banner_width: u16,
+
+    #[clap(long, env, value_parser = clap::value_parser!(u16).range(1..=512))]
+    #[clap(default_value = "31")]
+    banner_height: u16,
+
+    /// Default GIF banner background as RRGGBB.
+    #[clap(long, env)]
+    #[clap(default_value = "000000")]
+    banner_bg: mygif::Color,
+
+    /// Default GIF banner foreground as RRGGBB, up to 3 comma separated colors cycling per line.
+    #[clap(long, env)]
+    #[clap(default_value = "ffffff")]
+    banner_fg: gif_banner::Inks,
+
+    /// Name of this instance, embedded with the version as a comment in the GIF banner.
+    #[clap(long, env)]
+    instance_name: Option<String>,
+
+    /// Milliseconds each frame is sent before its second begins, to make up for latency.
+    #[clap(long, env, value_parser = clap::value_parser!(u64).range(0..=500))]
+    #[clap(default_value = "0")]
+    lead_time: u64,
+
+    /// Additionally send each frame half of the round trip time of its connection earlier.
+    #[clap(long, env)]
+    rtt_compensation: bool,
+
+    /// SNTP server to correct the local clock with, as host or host:port.
+    #[clap(long, env)]
+    ntp_server: Option<String>,
+
+    /// Seconds between measurements of the offset to `--ntp-server`.
+    #[clap(long, env, value_parser = clap::value_parser!(u64).range(16..=86400))]
+    #[clap(default_value = "64")]
+    ntp_interval: u64,
+
+    /// Seconds the offset to `--ntp-server` may reach before measurements are ignored as broken.
+    #[clap(long, env)]
+    ntp_max_offset: Option<u64>,
+
+    /// UDP address to answer SNTP requests on with the same time as the editions, e.g. 0.0.0.0:123.
+    #[clap(long, env)]
+    ntp_listen: Option<SocketAddr>,
+
+    /// Streams all clients together may have open, further ones get a 503 page.
+    #[clap(long, env)]
+    max_streams: Option<usize>,
+
+    /// Streams a single client address may have open.
+    #[clap(long, env)]
+    max_streams_per_client: Option<usize>,
+
+    /// Seconds after which a stream ends.
+    #[clap(long, env)]
+    max_session_lifetime: Option<u64>,
+
+    /// Comma separated proxy addresses or networks whose CF-Connecting-IP and X-Forwarded-For are believed.
+    #[clap(long, env, value_delimiter = ',')]
+    trusted_proxies: Vec<limits::Cidr>,
+
+    /// Seconds streams get to end on SIGTERM or Ctrl-C, also the delay clients are told to reload after.
+    #[clap(long, env, default_value_t = 5)]
+    shutdown_grace: u64,
+
+    /// PEM certificate chain to serve HTTPS with, reloaded when the file changes.
+    #[clap(long, env, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of `--tls-cert`.
+    #[clap(long, env, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// BDF or PSF bitmap font for the GIF banner instead of the built-in 6x10 font.
+    #[clap(long, env)]
+    font: Option<PathBuf>,
 }
 
-fn encode(previous_timestamp: i64, connection_count: usize) -> (i64, model::ClockData) {
-    let utc: DateTime<Utc> = Utc::now();
-    let utc = utc
-        .checked_add_signed(chrono::TimeDelta::new(1, 0).unwrap())
-        .unwrap();
+fn parse_time_format(format: &str) -> Result<String, String> {
+    use chrono::format::{Item, StrftimeItems};
 
-    let timestamp = utc.timestamp_millis();
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("{format:?} is not a valid strftime format"));
+    }
 
-    let jst = utc
-        .with_timezone(&Japan)
-        .format("%Y-%m-%d %H:%M:%S")
-        .to_string();
+    Ok(format.to_string())
+}
 
-    let ctx = model::Context {
-        previous_timestamp,
-        timestamp,
-        connection_count,
-        jst,
+#[cfg(unix)]
+async fn terminated() {
+    let mut terminate =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).unwrap();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn terminated() {
+    tokio::signal::ctrl_c().await.unwrap();
+}
+
+fn encode(ctx: &model::Context) -> model::ClockData {
+    let data = model::ClockData {
+        timestamp: ctx.timestamp,
+        previous_timestamp: ctx.previous_timestamp,
+        reload_after: ctx.reload_after,
+        zone: ctx.zone_label(),
+        connection_count: ctx.connections.total,
+        ..Default::default()
     };
 
-    (
-        timestamp,
-        model::ClockData {
-            svg: svg::encode(&ctx),
-            html: html::encode(&ctx),
-            select: select::encode(&ctx),
-            gif: gif_banner::encode(&ctx),
-            rtl: rtl::encode(&ctx),
-            dpu: dpu::encode(&ctx),
+    match ctx.editions {
+        model::Editions::GifBanner => model::ClockData {
+            gif: metrics::timed(metrics::Encoder::GifBanner, || gif_banner::encode(ctx)),
+            keyframes: Arc::new(model::Keyframes::new(ctx.clone())),
+            ..data
+        },
+        model::Editions::PngBanner => model::ClockData {
+            png: metrics::timed(metrics::Encoder::ApngBanner, || apng_banner::encode(ctx)),
+            keyframes: Arc::new(model::Keyframes::new(ctx.clone())),
+            ..data
         },
-    )
+        model::Editions::Pages => {
+            let data = model::ClockData {
+                svg: metrics::timed(metrics::Encoder::Svg, || svg::encode(ctx)),
+                html: metrics::timed(metrics::Encoder::Html, || html::encode(ctx)),
+                ..data
+            };
+
+            // Only the HTML and SVG editions subscribe with a finer precision.
+            match ctx.precision {
+                model::Precision::Decisecond => data,
+                model::Precision::Second => model::ClockData {
+                    select: metrics::timed(metrics::Encoder::Select, || select::encode(ctx)),
+                    rtl: metrics::timed(metrics::Encoder::Rtl, || rtl::encode(ctx)),
+                    dpu: metrics::timed(metrics::Encoder::Dpu, || dpu::encode(ctx)),
+                    text: metrics::timed(metrics::Encoder::Text, || text::encode(ctx)),
+                    text_big: metrics::timed(metrics::Encoder::TextBig, || text::encode_big(ctx)),
+                    json: metrics::timed(metrics::Encoder::Events, || events::encode(ctx)),
+                    ..data
+                },
+            }
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
     let c = Cli::parse();
 
-    gif_banner::initialization();
+    let font = match &c.font {
+        Some(path) => font::Font::load(path).unwrap_or_else(|e| {
+            Cli::command()
+                .error(
+                    clap::error::ErrorKind::InvalidValue,
+                    format!("failed to load {}: {e}", path.display()),
+                )
+                .exit()
+        }),
+        None => font::Font::builtin(),
+    };
+
+    api::initialization();
+
+    font::initialization(font);
+    gif_banner::initialization(
+        gif_banner::Palette {
+            bg: c.banner_bg,
+            fg: c.banner_fg,
+        },
+        c.instance_name.as_deref(),
+    );
 
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::INFO)
         .init();
 
-    let connection_counter = ConnectionCounter::new();
+    if let Some(server) = c.ntp_server.clone() {
+        time_source::initialization(
+            server,
+            std::time::Duration::from_secs(c.ntp_interval),
+            c.ntp_max_offset.map(std::time::Duration::from_secs),
+        );
+    }
 
-    let (clock_source, clock) = watch::channel(encode(0, 0).1);
+    let connection_counter = ConnectionCounter::new(limits::Limits {
+        max_streams: c.max_streams,
+        max_streams_per_client: c.max_streams_per_client,
+        max_lifetime: c.max_session_lifetime.map(std::time::Duration::from_secs),
+        trusted_proxies: c.trusted_proxies.clone(),
+    });
+
+    let clocks = ClockRegistry::new(
+        c.timezone,
+        &c.time_format,
+        c.zone_label.as_deref(),
+        mygif::Size::new(c.banner_width, c.banner_height),
+        clock_registry::Lead {
+            base: std::time::Duration::from_millis(c.lead_time),
+            compensate_rtt: c.rtt_compensation,
+        },
+        model::Tick {
+            utc: time_source::now(),
+            connections: Default::default(),
+            sync: None,
+            reload_after: None,
+        },
+    );
 
     let app = Router::new()
-        .route("/", get(html::handler))
+        .route("/", get(negotiation::handler))
         .route("/banner", get(gif_banner::banner_page_handler))
         .route("/banner.gif", get(gif_banner::gif_handler))
+        .route("/banner.png", get(apng_banner::png_handler))
+        .route("/banner.multipart", get(multipart_banner::handler))
         .route("/svg", get(svg::handler))
         .route("/rtl", get(rtl::handler))
         .route("/select", get(select::handler))
         .route("/dpu", get(dpu::handler))
-        .with_state((clock, connection_counter.clone()));
+        .route("/events", get(events::handler))
+        .route("/text", get(text::handler))
+        .route("/ws", any(ws::handler))
+        .route("/api/now", get(api::now_handler))
+        .route("/api/zones", get(api::zones_handler))
+        .route("/api/time-source", get(api::time_source_handler))
+        .route("/api/stats", get(api::stats_handler))
+        .route("/metrics", get(metrics::handler))
+        .with_state((clocks.clone(), connection_counter.clone()));
 
-    tokio::spawn(async move {
-        let mut previous_timestamp: i64 = 0;
+    let registry = clocks.clone();
 
-        loop {
-            let utc: DateTime<Utc> = Utc::now();
-            let time = encode(previous_timestamp, connection_counter.current());
-            let differencial = 1000 - utc.timestamp_subsec_millis();
+    tokio::spawn(async move { clocks.run(connection_counter).await });
 
-            tokio::time::sleep(std::time::Duration::from_millis(differencial as u64)).await;
-
-            clock_source.send(time.1).unwrap();
-            previous_timestamp = time.0;
-        }
-    });
+    if let Some(addr) = c.ntp_listen {
+        let socket = UdpSocket::bind(addr).await.unwrap();
+        tracing::info!("answering SNTP on {}", socket.local_addr().unwrap());
+        tokio::spawn(ntp_server::serve(socket));
+    }
 
     let listener = TcpListener::bind(c.listen).await.unwrap();
     tracing::info!("listening on {}", listener.local_addr().unwrap());
 
-    axum::serve(listener, app).await.unwrap();
+    let grace = std::time::Duration::from_secs(c.shutdown_grace);
+    let stopping = Arc::new(Notify::new());
+
+    let shutdown = {
+        let stopping = stopping.clone();
+
+        async move {
+            terminated().await;
+            tracing::info!("shutting down, waiting up to {grace:?} for streams to end");
+
+            // Every stream ends after its farewell frame, which lets the server drain.
+            registry.close(grace);
+            stopping.notify_one();
+        }
+    };
+
+    let app = app.into_make_service_with_connect_info::<peer::Peer>();
+
+    let server = match c.tls_cert.clone().zip(c.tls_key.clone()) {
+        Some((cert, key)) => {
+            let listener = tls::TlsListener::new(listener, tls::Identity { cert, key })
+                .unwrap_or_else(|e| {
+                    Cli::command()
+                        .error(clap::error::ErrorKind::InvalidValue, e)
+                        .exit()
+                });
+
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .into_future()
+                .boxed()
+        }
+        None => axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown)
+            .into_future()
+            .boxed(),
+    };
+
+    tokio::select! {
+        result = server => result.unwrap(),
+        _ = async {
+            stopping.notified().await;
+            tokio::time::sleep(grace).await;
+        } => tracing::warn!("grace period is over, dropping the remaining connections"),
+    }
 }
@@ -0,0 +1,356 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, http::header, response::IntoResponse};
+use bytes::Bytes;
+use futures::{Stream, TryStreamExt};
+use once_cell::sync::Lazy;
+
+use crate::{connection_counter::Edition, ClockRegistry, ConnectionCounter};
+
+/// Upper bounds in seconds, from a page glanced at to one left open all day.
+const SESSION_DURATION_BUCKETS: [f64; 8] =
+    [1.0, 10.0, 60.0, 300.0, 1800.0, 3600.0, 21600.0, 86400.0];
+
+/// Upper bounds in seconds, the finest precision being 100ms.
+const TICK_LATENESS_BUCKETS: [f64; 8] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0];
+
+static SESSIONS_OPENED: PerEdition = PerEdition::new();
+static SESSIONS_CLOSED: PerEdition = PerEdition::new();
+static BYTES_SENT: PerEdition = PerEdition::new();
+
+/// Nanoseconds spent and calls, indexed in the order of `Encoder::ALL`.
+static ENCODE: [(AtomicU64, AtomicU64); Encoder::ALL.len()] =
+    [const { (AtomicU64::new(0), AtomicU64::new(0)) }; Encoder::ALL.len()];
+
+/// Only the histograms are locked, as they are updated once per session or tick.
+static HISTOGRAMS: Lazy<Mutex<Histograms>> = Lazy::new(|| {
+    Mutex::new(Histograms {
+        session_duration: Histogram::new(&SESSION_DURATION_BUCKETS),
+        tick_lateness: Histogram::new(&TICK_LATENESS_BUCKETS),
+    })
+});
+
+/// The function `timed` accounts the time to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoder {
+    ApngBanner,
+    ApngBannerKeyframe,
+    Dpu,
+    Events,
+    GifBanner,
+    GifBannerKeyframe,
+    Html,
+    Rtl,
+    Select,
+    Svg,
+    Text,
+    TextBig,
+}
+
+impl Encoder {
+    pub const ALL: [Encoder; 12] = [
+        Encoder::ApngBanner,
+        Encoder::ApngBannerKeyframe,
+        Encoder::Dpu,
+        Encoder::Events,
+        Encoder::GifBanner,
+        Encoder::GifBannerKeyframe,
+        Encoder::Html,
+        Encoder::Rtl,
+        Encoder::Select,
+        Encoder::Svg,
+        Encoder::Text,
+        Encoder::TextBig,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoder::ApngBanner => "apng_banner::encode",
+            Encoder::ApngBannerKeyframe => "apng_banner::encode_keyframe",
+            Encoder::Dpu => "dpu::encode",
+            Encoder::Events => "events::encode",
+            Encoder::GifBanner => "gif_banner::encode",
+            Encoder::GifBannerKeyframe => "gif_banner::encode_keyframe",
+            Encoder::Html => "html::encode",
+            Encoder::Rtl => "rtl::encode",
+            Encoder::Select => "select::encode",
+            Encoder::Svg => "svg::encode",
+            Encoder::Text => "text::encode",
+            Encoder::TextBig => "text::encode_big",
+        }
+    }
+}
+
+/// A counter per edition, indexed in the order of `Edition::ALL`.
+struct PerEdition([AtomicU64; Edition::ALL.len()]);
+
+impl PerEdition {
+    const fn new() -> Self {
+        PerEdition([const { AtomicU64::new(0) }; Edition::ALL.len()])
+    }
+
+    fn add(&self, edition: Edition, value: u64) {
+        self.0[edition as usize].fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn get(&self, edition: Edition) -> u64 {
+        self.0[edition as usize].load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Per bound, of observations up to it, like the exposition format wants them.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(&mut self.counts) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {name} {help}").unwrap();
+        writeln!(out, "# TYPE {name} histogram").unwrap();
+
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}").unwrap();
+        }
+
+        writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", self.count).unwrap();
+        writeln!(out, "{name}_sum {}", self.sum).unwrap();
+        writeln!(out, "{name}_count {}", self.count).unwrap();
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Histograms {
+    session_duration: Histogram,
+    tick_lateness: Histogram,
+}
+
+fn render_per_edition(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl Fn(Edition) -> u64,
+) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+
+    for edition in Edition::ALL {
+        writeln!(
+            out,
+            "{name}{{edition=\"{}\"}} {}",
+            edition.name(),
+            value(edition)
+        )
+        .unwrap();
+    }
+}
+
+pub fn session_opened(edition: Edition) {
+    SESSIONS_OPENED.add(edition, 1);
+}
+
+pub fn session_closed(edition: Edition, duration: Duration) {
+    SESSIONS_CLOSED.add(edition, 1);
+
+    HISTOGRAMS
+        .lock()
+        .unwrap()
+        .session_duration
+        .observe(duration.as_secs_f64());
+}
+
+/// How long after its intended time the ticker published.
+pub fn tick_published(lateness: Duration) {
+    HISTOGRAMS
+        .lock()
+        .unwrap()
+        .tick_lateness
+        .observe(lateness.as_secs_f64());
+}
+
+/// Calls `encode`, accounting the time it took to `encoder`.
+pub fn timed<T>(encoder: Encoder, encode: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let encoded = encode();
+    let elapsed = started.elapsed().as_nanos() as u64;
+
+    let (nanos, calls) = &ENCODE[encoder as usize];
+    nanos.fetch_add(elapsed, Ordering::Relaxed);
+    calls.fetch_add(1, Ordering::Relaxed);
+
+    encoded
+}
+
+pub fn bytes_sent(edition: Edition, len: usize) {
+    BYTES_SENT.add(edition, len as u64);
+}
+
+/// Accounts everything `stream` yields to `edition`.
+pub fn count_bytes<S, E>(edition: Edition, stream: S) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    stream.inspect_ok(move |bytes| bytes_sent(edition, bytes.len()))
+}
+
+fn render(counter: &ConnectionCounter) -> String {
+    let breakdown = counter.breakdown();
+    // A copy, so that streams and the ticker don't wait for the formatting.
+    let histograms = HISTOGRAMS.lock().unwrap().clone();
+    let mut out = String::new();
+
+    render_per_edition(
+        &mut out,
+        "http_clock_active_streams",
+        "gauge",
+        "Streams currently open.",
+        |edition| breakdown.edition(edition) as u64,
+    );
+
+    render_per_edition(
+        &mut out,
+        "http_clock_sessions_opened_total",
+        "counter",
+        "Streams opened since start.",
+        |edition| SESSIONS_OPENED.get(edition),
+    );
+
+    render_per_edition(
+        &mut out,
+        "http_clock_sessions_closed_total",
+        "counter",
+        "Streams closed since start.",
+        |edition| SESSIONS_CLOSED.get(edition),
+    );
+
+    render_per_edition(
+        &mut out,
+        "http_clock_sent_bytes_total",
+        "counter",
+        "Bytes pushed to streams.",
+        |edition| BYTES_SENT.get(edition),
+    );
+
+    histograms.session_duration.render(
+        &mut out,
+        "http_clock_session_duration_seconds",
+        "How long closed streams were open.",
+    );
+
+    histograms.tick_lateness.render(
+        &mut out,
+        "http_clock_tick_lateness_seconds",
+        "How long after its intended time each tick was published.",
+    );
+
+    writeln!(
+        out,
+        "# HELP http_clock_encode_seconds Time spent rendering frames."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE http_clock_encode_seconds summary").unwrap();
+
+    for (encoder, (nanos, calls)) in Encoder::ALL.into_iter().zip(&ENCODE) {
+        let count = calls.load(Ordering::Relaxed);
+
+        // Keys that never rendered such a frame.
+        if count == 0 {
+            continue;
+        }
+
+        let function = encoder.name();
+        let sum = Duration::from_nanos(nanos.load(Ordering::Relaxed)).as_secs_f64();
+
+        writeln!(
+            out,
+            "http_clock_encode_seconds_sum{{function=\"{function}\"}} {sum}"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "http_clock_encode_seconds_count{{function=\"{function}\"}} {count}"
+        )
+        .unwrap();
+    }
+
+    out
+}
+
+/// Prometheus text exposition.
+pub async fn handler(
+    State((_, counter)): State<(ClockRegistry, ConnectionCounter)>,
+) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(&counter),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::new(&[0.25, 1.0]);
+        histogram.observe(0.25);
+        histogram.observe(0.5);
+        histogram.observe(2.0);
+
+        let mut out = String::new();
+        histogram.render(&mut out, "latency_seconds", "Latency.");
+
+        assert_eq!(
+            out,
+            "# HELP latency_seconds Latency.\n\
+             # TYPE latency_seconds histogram\n\
+             latency_seconds_bucket{le=\"0.25\"} 1\n\
+             latency_seconds_bucket{le=\"1\"} 2\n\
+             latency_seconds_bucket{le=\"+Inf\"} 3\n\
+             latency_seconds_sum 2.75\n\
+             latency_seconds_count 3\n"
+        );
+    }
+
+    #[test]
+    fn editions_and_encoders_index_their_counters() {
+        for (index, edition) in Edition::ALL.into_iter().enumerate() {
+            assert_eq!(edition as usize, index, "{}", edition.name());
+        }
+
+        for (index, encoder) in Encoder::ALL.into_iter().enumerate() {
+            assert_eq!(encoder as usize, index, "{}", encoder.name());
+        }
+    }
+}
@@ -1,17 +1,331 @@
-#[derive(Debug, Clone)]
+use std::sync::{Arc, OnceLock};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer};
+
+use crate::{connection_counter::Breakdown, mygif::Size};
+
+/// Frames of editions the key doesn't render are left empty.
+#[derive(Debug, Clone, Default)]
 pub struct ClockData {
+    pub timestamp: i64,
+    pub previous_timestamp: i64,
+    /// Set on the last frame, like `Context::reload_after`.
+    pub reload_after: Option<u64>,
+    pub zone: String,
+    pub connection_count: usize,
     pub html: bytes::Bytes,
     pub svg: bytes::Bytes,
     pub select: bytes::Bytes,
+    /// Only the glyphs which differ from the frame at `previous_timestamp`.
     pub gif: bytes::Bytes,
+    /// Like `gif`, `None` if no glyph changed.
+    pub png: Option<crate::apng_banner::Frame>,
+    /// Of the banner key, for subscribers which don't have the previous frame.
+    pub keyframes: Arc<Keyframes>,
     pub rtl: bytes::Bytes,
     pub dpu: bytes::Bytes,
+    pub text: bytes::Bytes,
+    pub text_big: bytes::Bytes,
+    /// `events::Payload` as JSON.
+    pub json: bytes::Bytes,
+}
+
+/// The whole banner of one tick, rendered once by whichever subscriber needs it first.
+///
+/// Most ticks need none, as only new subscribers and those which missed a frame ask for them.
+#[derive(Debug, Default)]
+pub struct Keyframes {
+    ctx: Option<Context>,
+    gif: OnceLock<bytes::Bytes>,
+    png: OnceLock<crate::apng_banner::Frame>,
+}
+
+impl Keyframes {
+    pub fn new(ctx: Context) -> Self {
+        Keyframes {
+            ctx: Some(ctx),
+            ..Default::default()
+        }
+    }
+
+    fn ctx(&self) -> &Context {
+        self.ctx.as_ref().expect("only banner keys have keyframes")
+    }
+
+    pub fn gif(&self) -> bytes::Bytes {
+        self.gif
+            .get_or_init(|| {
+                crate::metrics::timed(crate::metrics::Encoder::GifBannerKeyframe, || {
+                    crate::gif_banner::encode_keyframe(self.ctx())
+                })
+            })
+            .clone()
+    }
+
+    pub fn png(&self) -> crate::apng_banner::Frame {
+        self.png
+            .get_or_init(|| {
+                crate::metrics::timed(crate::metrics::Encoder::ApngBannerKeyframe, || {
+                    crate::apng_banner::encode_keyframe(self.ctx())
+                })
+            })
+            .clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tick {
+    pub utc: DateTime<Utc>,
+    pub connections: Arc<Breakdown>,
+    pub sync: Option<crate::time_source::Measurement>,
+    /// Set on the last tick before shutting down, in seconds.
+    pub reload_after: Option<u64>,
+}
+
+/// How often a clock ticks and how many fractional digits of a second it shows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Precision {
+    #[default]
+    Second,
+    Decisecond,
+}
+
+impl Precision {
+    /// The period of the fastest clock, every tick is a multiple of it.
+    pub const FINEST: TimeDelta = TimeDelta::milliseconds(100);
+
+    pub fn period(&self) -> TimeDelta {
+        match self {
+            Precision::Second => TimeDelta::seconds(1),
+            Precision::Decisecond => TimeDelta::milliseconds(100),
+        }
+    }
+
+    pub fn is_due(&self, utc: DateTime<Utc>) -> bool {
+        i64::from(utc.timestamp_subsec_millis()) % self.period().num_milliseconds() == 0
+    }
+}
+
+impl std::str::FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1s" => Ok(Precision::Second),
+            "100ms" => Ok(Precision::Decisecond),
+            _ => Err(format!("{s:?} is not a precision, use 1s or 100ms")),
+        }
+    }
+}
+
+/// Geometry of image editions. `size` is laid out first, then each pixel becomes `scale`x`scale`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Banner {
+    pub size: Size,
+    pub scale: u16,
+}
+
+impl Banner {
+    pub const fn screen_size(&self) -> Size {
+        Size::new(self.size.width * self.scale, self.size.height * self.scale)
+    }
+}
+
+/// Which editions a key renders, so that banners and pages don't pay for each other's frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Editions {
+    /// Every edition but the banners.
+    #[default]
+    Pages,
+    /// The GIF banner, which also depends on `ClockKey::banner`.
+    GifBanner,
+    /// The APNG and multipart banners, like `GifBanner`.
+    PngBanner,
+}
+
+/// Everything that makes two subscribers need differently rendered frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClockKey {
+    pub tz: Tz,
+    pub editions: Editions,
+    pub banner: Banner,
+    pub precision: Precision,
 }
 
 #[derive(Debug, Clone)]
 pub struct Context {
     pub previous_timestamp: i64,
     pub timestamp: i64,
-    pub connection_count: usize,
-    pub jst: String,
+    pub connections: Arc<Breakdown>,
+    pub utc: DateTime<Utc>,
+    pub tz: Tz,
+    pub editions: Editions,
+    pub banner: Banner,
+    pub precision: Precision,
+    pub time_format: Arc<str>,
+    /// The last SNTP measurement, `None` without `--ntp-server` or before the first one.
+    pub sync: Option<crate::time_source::Measurement>,
+    /// Seconds after which to reload, set when the server is shutting down and this is the last frame.
+    pub reload_after: Option<u64>,
+    /// Overrides the zone abbreviation, only set for the server-wide default zone.
+    pub zone_label: Option<Arc<str>>,
+}
+
+impl Context {
+    pub fn local_time(&self) -> String {
+        self.format(self.utc)
+    }
+
+    /// How far the time is corrected and when it was measured, with `--ntp-server` only.
+    pub fn sync_note(&self) -> Option<String> {
+        let (sync, server) = (self.sync?, crate::time_source::server()?);
+
+        Some(format!(
+            "{:+.1} ms from {server}, synced at {}",
+            sync.offset.num_microseconds().unwrap_or_default() as f64 / 1000.0,
+            sync.utc.with_timezone(&self.tz).format("%H:%M:%S")
+        ))
+    }
+
+    pub fn previous_local_time(&self) -> String {
+        self.format(self.previous_utc())
+    }
+
+    fn previous_utc(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.previous_timestamp).unwrap_or_default()
+    }
+
+    /// Sub-second precisions append the fraction to the last seconds, formats without them
+    /// don't show it.
+    fn format(&self, utc: DateTime<Utc>) -> String {
+        use chrono::format::{Item, Numeric, StrftimeItems};
+
+        let local = utc.with_timezone(&self.tz);
+        let mut items: Vec<Item> = StrftimeItems::new(&self.time_format).collect();
+
+        if self.precision == Precision::Decisecond {
+            let seconds = items
+                .iter()
+                .rposition(|item| matches!(item, Item::Numeric(Numeric::Second, _)));
+
+            if let Some(i) = seconds {
+                let tenths = utc.timestamp_subsec_millis() / 100;
+                items.insert(i + 1, Item::OwnedLiteral(format!(".{tenths}").into()));
+            }
+        }
+
+        local.format_with_items(items.into_iter()).to_string()
+    }
+
+    /// Abbreviation of the zone at this instant, e.g. "JST" or "CEST".
+    pub fn zone_label(&self) -> String {
+        self.zone_label_at(self.utc)
+    }
+
+    /// Abbreviation of the zone at `previous_timestamp`, which differs across DST changes.
+    pub fn previous_zone_label(&self) -> String {
+        self.zone_label_at(self.previous_utc())
+    }
+
+    fn zone_label_at(&self, utc: DateTime<Utc>) -> String {
+        match &self.zone_label {
+            Some(label) => label.to_string(),
+            None => utc.with_timezone(&self.tz).format("%Z").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClockQuery {
+    pub tz: Option<Tz>,
+    /// Only honoured by the HTML and SVG editions.
+    #[serde(default, deserialize_with = "from_str")]
+    pub precision: Option<Precision>,
+}
+
+pub fn render_head(template: &str, zone: &str) -> bytes::Bytes {
+    bytes::Bytes::from(template.replace("{zone}", zone))
+}
+
+/// Deserializes an optional query parameter through its `FromStr` implementation.
+pub fn from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|v| v.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn context(utc: DateTime<Utc>, precision: Precision, time_format: &str) -> Context {
+        Context {
+            previous_timestamp: 0,
+            timestamp: utc.timestamp_millis(),
+            connections: Default::default(),
+            utc,
+            tz: chrono_tz::Asia::Tokyo,
+            editions: Editions::Pages,
+            banner: Banner {
+                size: Size::new(88, 31),
+                scale: 1,
+            },
+            precision,
+            time_format: time_format.into(),
+            sync: None,
+            reload_after: None,
+            zone_label: None,
+        }
+    }
+
+    #[test]
+    fn decisecond_appends_tenths_to_seconds() {
+        let utc =
+            Utc.with_ymd_and_hms(2026, 10, 17, 12, 34, 56).unwrap() + TimeDelta::milliseconds(700);
+
+        assert_eq!(
+            context(utc, Precision::Second, "%Y-%m-%d %H:%M:%S").local_time(),
+            "2026-10-17 21:34:56"
+        );
+        assert_eq!(
+            context(utc, Precision::Decisecond, "%Y-%m-%d %H:%M:%S").local_time(),
+            "2026-10-17 21:34:56.7"
+        );
+        assert_eq!(
+            context(utc, Precision::Decisecond, "%T %Z").local_time(),
+            "21:34:56.7 JST"
+        );
+    }
+
+    #[test]
+    fn decisecond_only_follows_the_last_seconds() {
+        let utc =
+            Utc.with_ymd_and_hms(2026, 10, 17, 12, 34, 56).unwrap() + TimeDelta::milliseconds(700);
+        let local_time = |format| context(utc, Precision::Decisecond, format).local_time();
+
+        assert_eq!(local_time("%T"), "21:34:56.7");
+        assert_eq!(local_time("%S (%T)"), "56 (21:34:56.7)");
+        assert_eq!(local_time("%T %%S"), "21:34:56.7 %S");
+        assert_eq!(local_time("%%S %H:%M"), "%S 21:34");
+    }
+
+    #[test]
+    fn only_whole_seconds_are_due_for_second_precision() {
+        let second = Utc.with_ymd_and_hms(2026, 10, 17, 12, 34, 56).unwrap();
+        let tenth = second + TimeDelta::milliseconds(100);
+
+        assert!(Precision::Second.is_due(second));
+        assert!(!Precision::Second.is_due(tenth));
+        assert!(Precision::Decisecond.is_due(second));
+        assert!(Precision::Decisecond.is_due(tenth));
+    }
 }
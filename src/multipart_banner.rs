@@ -0,0 +1,206 @@
+use std::io::Cursor;
+
+use crate::{
+    apng_banner::{image_chunks, Frame},
+    connection_counter::Edition,
+    gif_banner::{BannerQuery, Palette},
+    limits::Admission,
+    metrics,
+    model::Editions,
+    mygif::Size,
+    mypng::{Chunk, SIGNATURE},
+    peer::Peer,
+    Clock, ClockRegistry, ConnectionCounter,
+};
+
+use async_stream::try_stream;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, HeaderName},
+    response::IntoResponse,
+};
+use binrw::BinWrite;
+use bytes::Bytes;
+use futures::Stream;
+
+const BOUNDARY: &str = "frame";
+
+fn header(size: Size, palette: &Palette, alpha: u8) -> Bytes {
+    let mut buffer = Cursor::new(vec![]);
+
+    SIGNATURE.write_be(&mut buffer).unwrap();
+    image_chunks(size, palette, alpha)
+        .write_be(&mut buffer)
+        .unwrap();
+
+    Bytes::from(buffer.into_inner())
+}
+
+/// Completes `header` (signature, `IHDR`, `PLTE` and `tRNS`) into a PNG showing `frame`.
+fn still(header: &[u8], frame: &Frame) -> Vec<u8> {
+    let mut buffer = Cursor::new(header.to_vec());
+    buffer.set_position(header.len() as u64);
+
+    let chunks = [
+        Chunk {
+            kind: *b"IDAT",
+            data: frame.data.to_vec(),
+        },
+        Chunk {
+            kind: *b"IEND",
+            data: vec![],
+        },
+    ];
+
+    chunks.write_be(&mut buffer).unwrap();
+
+    buffer.into_inner()
+}
+
+/// One body part, followed by the next boundary so browsers show it right away.
+fn part(header: &[u8], frame: &Frame) -> Bytes {
+    let png = still(header, frame);
+
+    let mut part = format!(
+        "Content-Type: image/png\r\nContent-Length: {}\r\n\r\n",
+        png.len()
+    )
+    .into_bytes();
+
+    part.extend(png);
+    part.extend(format!("\r\n--{BOUNDARY}\r\n").as_bytes());
+
+    Bytes::from(part)
+}
+
+fn stream(
+    mut clock: Clock,
+    counter: ConnectionCounter,
+    header: Bytes,
+) -> impl Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Send + Sync>>> {
+    try_stream! {
+        let _session = counter.acquire(Edition::Multipart, clock.key().tz);
+        yield Bytes::from(format!("--{BOUNDARY}\r\n"));
+
+        let keyframes = clock.borrow_and_update().keyframes.clone();
+        yield part(&header, &keyframes.png());
+
+        loop {
+            if clock.changed().await.is_err() {
+                break;
+            }
+            let keyframes = clock.borrow_and_update().keyframes.clone();
+            yield part(&header, &keyframes.png());
+        }
+    }
+}
+
+pub async fn handler(
+    headers: HeaderMap,
+    Query(query): Query<BannerQuery>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    admission: Admission,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
+) -> impl IntoResponse {
+    let key = query.key(&clocks, Editions::PngBanner);
+
+    let stream = stream(
+        clocks.subscribe(key, &peer, admission.deadline()),
+        counter,
+        header(
+            key.banner.screen_size(),
+            &query.palette(),
+            query.alpha.unwrap_or(0xFF),
+        ),
+    );
+    let body = Body::from_stream(metrics::count_bytes(
+        Edition::Multipart,
+        admission.limit(stream),
+    ));
+
+    let is_cloudflare = headers.contains_key("cf-ray");
+    let content_type = format!("multipart/x-mixed-replace; boundary={BOUNDARY}");
+
+    let headers = [
+        (
+            header::CONTENT_TYPE,
+            if is_cloudflare {
+                "application/grpc".to_string()
+            } else {
+                content_type.clone()
+            },
+        ),
+        (
+            HeaderName::from_static("x-original-content-type"),
+            content_type,
+        ),
+    ];
+
+    (headers, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use binrw::BinRead;
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::{
+        apng_banner::encode_keyframe,
+        gif_banner::tests::{context, expected, palette, setup},
+        model::Banner,
+        mypng::{undo_deflate, ImageHeader},
+    };
+
+    #[test]
+    fn part_is_a_complete_png() {
+        setup();
+
+        let banner = Banner {
+            size: Size::new(88, 31),
+            scale: 2,
+        };
+        let screen = banner.screen_size();
+        let ctx = context(
+            Utc.with_ymd_and_hms(2026, 10, 17, 12, 34, 56).unwrap(),
+            banner,
+        );
+
+        let part = part(&header(screen, &palette(), 0xFF), &encode_keyframe(&ctx));
+
+        let (headers, rest) =
+            part.split_at(part.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4);
+        let headers = std::str::from_utf8(headers).unwrap();
+
+        let length: usize = headers
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let (png, trailer) = rest.split_at(length);
+        assert!(headers.starts_with("Content-Type: image/png\r\n"));
+        assert_eq!(trailer, format!("\r\n--{BOUNDARY}\r\n").as_bytes());
+        assert_eq!(png[..SIGNATURE.len()], SIGNATURE);
+
+        let mut reader = Cursor::new(&png[SIGNATURE.len()..]);
+        let mut chunks = vec![];
+
+        while reader.position() < reader.get_ref().len() as u64 {
+            chunks.push(Chunk::read(&mut reader).unwrap());
+        }
+
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|chunk| &chunk.kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"PLTE", b"tRNS", b"IDAT", b"IEND"]);
+
+        let ihdr = ImageHeader::read(&mut Cursor::new(&chunks[0].data)).unwrap();
+        assert_eq!((ihdr.width, ihdr.height), (176, 62));
+
+        assert_eq!(
+            undo_deflate(&chunks[3].data, screen.width as usize).unwrap(),
+            expected(banner, ["2026-10-17", "12:34:56 UTC"])
+        );
+    }
+}
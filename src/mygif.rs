@@ -70,15 +70,19 @@ impl BinRead for Version {
         _endian: binrw::Endian,
         _args: Self::Args<'_>,
     ) -> binrw::BinResult<Self> {
+        let pos = reader.stream_position()?;
         let mut version = [0u8; 3];
 
         reader.read_exact(&mut version)?;
 
-        Ok(match &version {
-            b"87a" => Self::GIF87a,
-            b"89a" => Self::GIF89a,
-            version => panic!("{version:?} is not supported."),
-        })
+        match &version {
+            b"87a" => Ok(Self::GIF87a),
+            b"89a" => Ok(Self::GIF89a),
+            _ => Err(binrw::Error::BadMagic {
+                pos,
+                found: Box::new(version),
+            }),
+        }
     }
 }
 
@@ -101,7 +105,7 @@ impl BinWrite for Version {
 }
 
 #[binrw]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -114,6 +118,22 @@ impl Color {
     }
 }
 
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    /// Parses `RRGGBB`, optionally prefixed with `#`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+
+        match u32::from_str_radix(hex, 16) {
+            Ok(v) if hex.len() == 6 => {
+                Ok(Color::from_rgb((v >> 16) as u8, (v >> 8) as u8, v as u8))
+            }
+            _ => Err(format!("{s:?} is not a RRGGBB color")),
+        }
+    }
+}
+
 #[bitfield(u8)]
 pub struct ImagePacked {
     #[bits(3)]
@@ -172,7 +192,7 @@ impl BinWrite for ImagePacked {
 }
 
 #[binrw]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct Position {
     pub left: u16,
     pub top: u16,
@@ -185,7 +205,7 @@ impl Position {
 }
 
 #[binrw]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
 pub struct Size {
     pub width: u16,
     pub height: u16,
@@ -224,14 +244,55 @@ pub struct Image {
     ))]
     pub local_color_table: Vec<Color>,
 
-    #[bw(calc(crate::mygif::LZW_CODESIZE))]
-    pub _lzw_minimum_code_size: u8,
+    /// Usually `LZW_CODESIZE`, see there.
+    pub lzw_minimum_code_size: u8,
 
     #[br(parse_with = chunked_binary_parser)]
     #[bw(write_with = chunked_binary_writer)]
     pub lzw_binary: Vec<u8>,
 }
 
+// The server only ever writes GIFs, decoding is for validating what it wrote.
+#[cfg(test)]
+impl Image {
+    /// Decompresses the color index of every pixel, row by row from the top.
+    pub fn pixels(&self) -> binrw::BinResult<Vec<u8>> {
+        let (width, height) = (self.size.width as usize, self.size.height as usize);
+        let mut pixels = undo_lzw(&self.lzw_binary, self.lzw_minimum_code_size)?;
+
+        if pixels.len() < width * height {
+            return Err(binrw::Error::AssertFail {
+                pos: 0,
+                message: format!(
+                    "{} pixels are decoded, but the image is {width}x{height}",
+                    pixels.len()
+                ),
+            });
+        }
+
+        pixels.truncate(width * height);
+
+        if !self.packed.interlace_flag() {
+            return Ok(pixels);
+        }
+
+        // Interlaced rows are stored in 4 passes.
+        let order = (0..height)
+            .step_by(8)
+            .chain((4..height).step_by(8))
+            .chain((2..height).step_by(4))
+            .chain((1..height).step_by(2));
+
+        let mut deinterlaced = vec![0u8; width * height];
+
+        for (row, y) in pixels.chunks(width).zip(order) {
+            deinterlaced[y * width..(y + 1) * width].copy_from_slice(row);
+        }
+
+        Ok(deinterlaced)
+    }
+}
+
 /// WARNING: NON 8 CODESIZE IS NOT SUPPORTED BY FIREFOX
 pub const LZW_CODESIZE: u8 = 8;
 
@@ -248,6 +309,27 @@ pub fn do_lzw(data: &[u8]) -> Vec<u8> {
     bin
 }
 
+#[cfg(test)]
+pub fn undo_lzw(mut data: &[u8], code_size: u8) -> std::io::Result<Vec<u8>> {
+    use lzw::{Decoder, LsbReader};
+
+    let mut decoder = Decoder::new(LsbReader::new(), code_size);
+    let mut pixels = Vec::new();
+
+    while !data.is_empty() {
+        let (consumed, bytes) = decoder.decode_bytes(data)?;
+        pixels.extend_from_slice(bytes);
+
+        if consumed == 0 {
+            break;
+        }
+
+        data = &data[consumed..];
+    }
+
+    Ok(pixels)
+}
+
 #[binrw::writer(writer)]
 fn chunked_binary_writer(binary: &Vec<u8>) -> binrw::BinResult<()> {
     let binary = binary.as_slice();
@@ -293,6 +375,47 @@ fn chunked_binary_parser() -> binrw::BinResult<Vec<u8>> {
     Ok(binary)
 }
 
+/// Unlike `chunked_binary_parser`, keeps the boundaries of each sub-block.
+#[binrw::parser(reader)]
+fn sub_blocks_parser() -> binrw::BinResult<Vec<Vec<u8>>> {
+    let mut sub_blocks = vec![];
+    let mut block_size = [0];
+
+    loop {
+        reader.read_exact(&mut block_size)?;
+
+        if block_size[0] == 0 {
+            break;
+        }
+
+        let mut sub_block = vec![0u8; block_size[0].into()];
+        reader.read_exact(&mut sub_block)?;
+
+        sub_blocks.push(sub_block);
+    }
+
+    Ok(sub_blocks)
+}
+
+#[binrw::writer(writer)]
+fn sub_blocks_writer(sub_blocks: &Vec<Vec<u8>>) -> binrw::BinResult<()> {
+    for sub_block in sub_blocks {
+        if sub_block.is_empty() || sub_block.len() > 0xFF {
+            return Err(binrw::Error::AssertFail {
+                pos: writer.stream_position()?,
+                message: format!("sub-block length must be 1..=255, got {}", sub_block.len()),
+            });
+        }
+
+        writer.write_all(&[sub_block.len() as u8])?;
+        writer.write_all(sub_block)?;
+    }
+
+    writer.write_all(&[0x00])?;
+
+    Ok(())
+}
+
 #[binrw::parser(reader, endian)]
 fn block_parser() -> binrw::BinResult<Vec<Block>> {
     let mut blocks = Vec::new();
@@ -395,11 +518,108 @@ impl BinWrite for GraphicControlExtensionPacked {
     }
 }
 
+#[binrw]
+#[derive(Debug)]
+pub struct ApplicationExtension {
+    #[bw(calc(0x0B))]
+    pub _block_size: u8,
+
+    pub identifier: [u8; 8],
+
+    pub authentication_code: [u8; 3],
+
+    #[br(parse_with = sub_blocks_parser)]
+    #[bw(write_with = sub_blocks_writer)]
+    pub data: Vec<Vec<u8>>,
+}
+
+// A live banner must not replay once the stream ends, so only the tests use this.
+#[cfg(test)]
+impl ApplicationExtension {
+    /// NETSCAPE2.0 looping extension. 0 loops forever.
+    pub fn netscape_looping(loop_count: u16) -> Self {
+        let [low, high] = loop_count.to_le_bytes();
+
+        Self {
+            identifier: *b"NETSCAPE",
+            authentication_code: *b"2.0",
+            data: vec![vec![0x01, low, high]],
+        }
+    }
+
+    pub fn loop_count(&self) -> Option<u16> {
+        match (
+            &self.identifier,
+            &self.authentication_code,
+            self.data.as_slice(),
+        ) {
+            (b"NETSCAPE", b"2.0", [sub_block, ..]) => match sub_block.as_slice() {
+                [0x01, low, high] => Some(u16::from_le_bytes([*low, *high])),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[binrw]
+#[derive(Debug)]
+pub struct CommentExtension {
+    #[br(parse_with = chunked_binary_parser)]
+    #[bw(write_with = chunked_binary_writer)]
+    pub comment: Vec<u8>,
+}
+
+#[binrw]
+#[derive(Debug)]
+pub struct PlainTextExtension {
+    #[bw(calc(0x0C))]
+    pub _block_size: u8,
+
+    pub grid_position: Position,
+
+    pub grid_size: Size,
+
+    pub cell_width: u8,
+
+    pub cell_height: u8,
+
+    pub foreground_color_index: u8,
+
+    pub background_color_index: u8,
+
+    #[br(parse_with = chunked_binary_parser)]
+    #[bw(write_with = chunked_binary_writer)]
+    pub text: Vec<u8>,
+}
+
+/// Any extension this module doesn't understand, kept as is.
+#[binrw]
+#[derive(Debug)]
+pub struct UnknownExtension {
+    pub label: u8,
+
+    #[br(parse_with = sub_blocks_parser)]
+    #[bw(write_with = sub_blocks_writer)]
+    pub data: Vec<Vec<u8>>,
+}
+
 #[binrw]
 #[derive(Debug)]
 pub enum Extension {
     #[brw(magic(0xF9u8))]
     GraphicsControlExtension(GraphicsControlExtension),
+
+    #[brw(magic(0xFFu8))]
+    ApplicationExtension(ApplicationExtension),
+
+    #[brw(magic(0xFEu8))]
+    CommentExtension(CommentExtension),
+
+    #[brw(magic(0x01u8))]
+    PlainTextExtension(PlainTextExtension),
+
+    Unknown(UnknownExtension),
 }
 
 #[binrw]
@@ -446,3 +666,84 @@ pub struct Gif {
     #[br(parse_with = block_parser)]
     pub blocks: Vec<Block>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lzw_round_trip() {
+        let pixels: Vec<u8> = (0..1000u32).map(|v| (v * v % 7) as u8).collect();
+
+        assert_eq!(undo_lzw(&do_lzw(&pixels), LZW_CODESIZE).unwrap(), pixels);
+    }
+
+    #[test]
+    fn extensions_round_trip() {
+        let blocks = vec![
+            Block::Extension(Extension::ApplicationExtension(
+                ApplicationExtension::netscape_looping(3),
+            )),
+            Block::Extension(Extension::CommentExtension(CommentExtension {
+                comment: b"x".repeat(300),
+            })),
+            Block::Extension(Extension::PlainTextExtension(PlainTextExtension {
+                grid_position: Position::new(1, 2),
+                grid_size: Size::new(30, 10),
+                cell_width: 6,
+                cell_height: 10,
+                foreground_color_index: 1,
+                background_color_index: 0,
+                text: b"12:34".to_vec(),
+            })),
+            Block::Extension(Extension::Unknown(UnknownExtension {
+                label: 0x42,
+                data: vec![vec![1, 2, 3], vec![4]],
+            })),
+            Block::Trailer(()),
+        ];
+
+        let mut writer = std::io::Cursor::new(vec![]);
+        blocks.write_le(&mut writer).unwrap();
+
+        let mut reader = std::io::Cursor::new(writer.into_inner());
+        let blocks = block_parser(&mut reader, binrw::Endian::Little, ()).unwrap();
+
+        let Block::Extension(Extension::ApplicationExtension(application)) = &blocks[0] else {
+            panic!("expected an application extension: {:?}", blocks[0]);
+        };
+
+        let Block::Extension(Extension::CommentExtension(comment)) = &blocks[1] else {
+            panic!("expected a comment extension: {:?}", blocks[1]);
+        };
+
+        let Block::Extension(Extension::PlainTextExtension(plain_text)) = &blocks[2] else {
+            panic!("expected a plain text extension: {:?}", blocks[2]);
+        };
+
+        let Block::Extension(Extension::Unknown(unknown)) = &blocks[3] else {
+            panic!("expected an unknown extension: {:?}", blocks[3]);
+        };
+
+        assert_eq!(application.loop_count(), Some(3));
+        assert_eq!(comment.comment, b"x".repeat(300));
+        assert_eq!(plain_text.grid_size, Size::new(30, 10));
+        assert_eq!(plain_text.text, b"12:34");
+        assert_eq!(
+            (unknown.label, unknown.data.clone()),
+            (0x42, vec![vec![1, 2, 3], vec![4]])
+        );
+    }
+
+    #[test]
+    fn unknown_version_is_an_error() {
+        let mut reader = std::io::Cursor::new(b"GIF88a\x01\x00\x01\x00\x00\x00\x00\x3B");
+
+        let error = Gif::read(&mut reader).unwrap_err();
+
+        assert!(matches!(
+            error.root_cause(),
+            binrw::Error::BadMagic { pos: 3, .. }
+        ));
+    }
+}
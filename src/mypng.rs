@@ -0,0 +1,150 @@
+use std::io::{Cursor, Write};
+
+use binrw::{binrw, BinWrite};
+
+pub const SIGNATURE: [u8; 8] = *b"\x89PNG\r\n\x1a\n";
+
+/// Color type of an image whose pixels are indexes into `PLTE`.
+pub const COLOR_TYPE_INDEXED: u8 = 3;
+
+/// `fcTL` may not use the full `u32`, 2^31-1 is the largest number of frames.
+pub const MAX_FRAMES: u32 = i32::MAX as u32;
+
+fn checksum(kind: &[u8; 4], data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    #[br(temp)]
+    #[bw(calc = data.len() as u32)]
+    length: u32,
+
+    pub kind: [u8; 4],
+
+    #[br(count = length)]
+    pub data: Vec<u8>,
+
+    #[br(temp, assert(crc == checksum(&kind, &data), "CRC mismatch in {:?} chunk", kind))]
+    #[bw(calc = checksum(kind, data))]
+    crc: u32,
+}
+
+impl Chunk {
+    pub fn new<T>(kind: &[u8; 4], body: &T) -> Self
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        let mut buffer = Cursor::new(vec![]);
+        body.write_be(&mut buffer).unwrap();
+
+        Chunk {
+            kind: *kind,
+            data: buffer.into_inner(),
+        }
+    }
+}
+
+/// `IHDR`
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Clone)]
+pub struct ImageHeader {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace_method: u8,
+}
+
+/// `acTL`
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Clone)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+    /// 0 loops forever.
+    pub num_plays: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[binrw]
+#[brw(repr = u8)]
+pub enum DisposeOp {
+    None = 0,
+    Background = 1,
+    Previous = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[binrw]
+#[brw(repr = u8)]
+pub enum BlendOp {
+    Source = 0,
+    Over = 1,
+}
+
+/// `fcTL`, shares its sequence numbers with `fdAT`.
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Clone)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+/// `fdAT`, the `IDAT` of every frame but the first.
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Clone)]
+pub struct FrameData {
+    pub sequence_number: u32,
+
+    #[br(parse_with = binrw::helpers::until_eof)]
+    pub data: Vec<u8>,
+}
+
+/// Filters every row with `None` and deflates them, as `IDAT` and `fdAT` expect.
+pub fn do_deflate(pixels: &[u8], width: usize) -> Vec<u8> {
+    use flate2::{write::ZlibEncoder, Compression};
+
+    let mut encoder = ZlibEncoder::new(vec![], Compression::best());
+
+    for row in pixels.chunks(width) {
+        encoder.write_all(&[0x00]).unwrap();
+        encoder.write_all(row).unwrap();
+    }
+
+    encoder.finish().unwrap()
+}
+
+#[cfg(test)]
+pub fn undo_deflate(data: &[u8], width: usize) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut filtered = vec![];
+    flate2::read::ZlibDecoder::new(data).read_to_end(&mut filtered)?;
+
+    filtered
+        .chunks(width + 1)
+        .map(|row| match row.split_first() {
+            Some((0x00, pixels)) => Ok(pixels),
+            _ => Err(std::io::Error::other("only unfiltered rows are supported")),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|rows| rows.concat())
+}
@@ -0,0 +1,136 @@
+use crate::{events, gif_banner, html, text, ClockRegistry, ConnectionCounter};
+
+use axum::{
+    extract::{Request, State},
+    handler::Handler,
+    http::{header, HeaderMap, HeaderValue},
+    response::Response,
+};
+
+/// The handler `/` hands a request to, not to be confused with `connection_counter::Edition`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Negotiated {
+    Html,
+    Events,
+    Json,
+    Gif,
+    Text,
+}
+
+impl Negotiated {
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "text/html" => Some(Negotiated::Html),
+            "text/event-stream" => Some(Negotiated::Events),
+            "application/json" => Some(Negotiated::Json),
+            "image/gif" => Some(Negotiated::Gif),
+            "text/plain" => Some(Negotiated::Text),
+            _ => None,
+        }
+    }
+}
+
+/// The most preferred edition named in `Accept`, wildcards don't count.
+fn accepted(headers: &HeaderMap) -> Option<Negotiated> {
+    let accept = headers.get(header::ACCEPT)?.to_str().ok()?;
+
+    let mut ranges: Vec<(Negotiated, f32)> = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let edition = Negotiated::from_media_type(&params.next()?.to_ascii_lowercase())?;
+
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse().ok())?;
+
+            Some((edition, quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+
+    // Stable, so equally preferred ranges keep their order.
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges.first().map(|(edition, _)| *edition)
+}
+
+fn choose(headers: &HeaderMap) -> Negotiated {
+    match accepted(headers) {
+        Some(edition) => edition,
+        None if text::is_terminal(headers) => Negotiated::Text,
+        None => Negotiated::Html,
+    }
+}
+
+/// Serves whichever edition suits the client on `/`, HTML unless told otherwise.
+pub async fn handler(
+    State(state): State<(ClockRegistry, ConnectionCounter)>,
+    request: Request,
+) -> Response {
+    let mut response = match choose(request.headers()) {
+        Negotiated::Html => html::handler.call(request, state).await,
+        Negotiated::Events => events::handler.call(request, state).await,
+        Negotiated::Json => events::snapshot_handler.call(request, state).await,
+        Negotiated::Gif => gif_banner::gif_handler.call(request, state).await,
+        Negotiated::Text => text::handler.call(request, state).await,
+    };
+
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept, user-agent"));
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(header::HeaderName, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn browsers_get_html() {
+        let firefox = headers(&[
+            (
+                header::USER_AGENT,
+                "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0",
+            ),
+            (
+                header::ACCEPT,
+                "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+            ),
+        ]);
+
+        assert_eq!(choose(&firefox), Negotiated::Html);
+        assert_eq!(choose(&HeaderMap::new()), Negotiated::Html);
+    }
+
+    #[test]
+    fn accept_wins_over_user_agent() {
+        let curl = headers(&[(header::USER_AGENT, "curl/8.10.1"), (header::ACCEPT, "*/*")]);
+        assert_eq!(choose(&curl), Negotiated::Text);
+
+        let curl_json = headers(&[
+            (header::USER_AGENT, "curl/8.10.1"),
+            (header::ACCEPT, "application/json"),
+        ]);
+        assert_eq!(choose(&curl_json), Negotiated::Json);
+    }
+
+    #[test]
+    fn quality_orders_ranges() {
+        let accept = headers(&[(
+            header::ACCEPT,
+            "text/html;q=0.5, image/gif, text/event-stream;q=0.9",
+        )]);
+        assert_eq!(choose(&accept), Negotiated::Gif);
+
+        let refused = headers(&[(header::ACCEPT, "application/json;q=0, text/plain;q=0.1")]);
+        assert_eq!(choose(&refused), Negotiated::Text);
+    }
+}
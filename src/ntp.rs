@@ -0,0 +1,188 @@
+use binrw::{binrw, BinRead, BinWrite};
+use bitfield_struct::bitfield;
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// Seconds from the NTP era (1900-01-01) to the Unix epoch.
+const UNIX_EPOCH: i64 = 2_208_988_800;
+
+pub const PACKET_SIZE: usize = 48;
+
+pub const VERSION: u8 = 4;
+
+pub const MODE_CLIENT: u8 = 3;
+pub const MODE_SERVER: u8 = 4;
+
+/// Leap indicator of a server which isn't synchronized.
+pub const LEAP_UNKNOWN: u8 = 3;
+
+#[bitfield(u8)]
+pub struct Flags {
+    #[bits(3)]
+    pub mode: u8,
+
+    #[bits(3)]
+    pub version: u8,
+
+    #[bits(2)]
+    pub leap: u8,
+}
+
+impl BinRead for Flags {
+    type Args<'a> = ();
+
+    fn read_options<R: std::io::Read + std::io::Seek>(
+        reader: &mut R,
+        _endian: binrw::Endian,
+        _args: Self::Args<'_>,
+    ) -> binrw::BinResult<Self> {
+        let mut packed = [0u8; 1];
+        reader.read_exact(&mut packed)?;
+        Ok(Flags::from_bits(packed[0]))
+    }
+}
+
+impl BinWrite for Flags {
+    type Args<'a> = ();
+
+    fn write_options<W: std::io::Write + std::io::Seek>(
+        &self,
+        writer: &mut W,
+        _endian: binrw::Endian,
+        _args: Self::Args<'_>,
+    ) -> binrw::BinResult<()> {
+        writer.write_all(&[self.into_bits()])?;
+        Ok(())
+    }
+}
+
+/// Seconds since 1900 as 32.32 fixed point.
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timestamp {
+    pub seconds: u32,
+    pub fraction: u32,
+}
+
+impl Timestamp {
+    pub fn is_zero(&self) -> bool {
+        *self == Timestamp::default()
+    }
+}
+
+impl From<DateTime<Utc>> for Timestamp {
+    fn from(utc: DateTime<Utc>) -> Self {
+        let nanos = u64::from(utc.timestamp_subsec_nanos());
+
+        Timestamp {
+            seconds: (utc.timestamp() + UNIX_EPOCH) as u32,
+            fraction: ((nanos << 32) / 1_000_000_000) as u32,
+        }
+    }
+}
+
+impl From<Timestamp> for DateTime<Utc> {
+    /// Between 1968 and 2104, as RFC 4330 section 3 suggests.
+    fn from(timestamp: Timestamp) -> Self {
+        let nanos = (u64::from(timestamp.fraction) * 1_000_000_000) >> 32;
+
+        // Without the most significant bit, the seconds count from 2036-02-07 in era 1.
+        let seconds = match timestamp.seconds & 0x8000_0000 {
+            0 => i64::from(timestamp.seconds) + (1 << 32),
+            _ => i64::from(timestamp.seconds),
+        };
+
+        DateTime::from_timestamp(seconds - UNIX_EPOCH, nanos as u32).unwrap_or_default()
+    }
+}
+
+/// The SNTPv4 packet (RFC 4330), without extension fields.
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Clone)]
+pub struct Packet {
+    pub flags: Flags,
+    pub stratum: u8,
+    pub poll: i8,
+    pub precision: i8,
+    /// 16.16 fixed point seconds.
+    pub root_delay: u32,
+    /// 16.16 fixed point seconds.
+    pub root_dispersion: u32,
+    pub reference_id: [u8; 4],
+    pub reference_timestamp: Timestamp,
+    pub originate_timestamp: Timestamp,
+    pub receive_timestamp: Timestamp,
+    pub transmit_timestamp: Timestamp,
+}
+
+impl Packet {
+    pub fn request(transmit: DateTime<Utc>) -> Self {
+        Packet {
+            flags: Flags::new().with_version(VERSION).with_mode(MODE_CLIENT),
+            stratum: 0,
+            poll: 0,
+            precision: 0,
+            root_delay: 0,
+            root_dispersion: 0,
+            reference_id: [0; 4],
+            reference_timestamp: Timestamp::default(),
+            originate_timestamp: Timestamp::default(),
+            receive_timestamp: Timestamp::default(),
+            transmit_timestamp: transmit.into(),
+        }
+    }
+}
+
+/// What one exchange with a server tells about the local clock.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    /// Add to the local clock to get the server's.
+    pub offset: TimeDelta,
+    /// Round trip time, without the time the server took to answer.
+    pub delay: TimeDelta,
+    pub stratum: u8,
+}
+
+impl Sample {
+    /// `originate` and `destination` are when the request left and the response arrived by the local clock.
+    pub fn new(response: &Packet, originate: DateTime<Utc>, destination: DateTime<Utc>) -> Self {
+        let receive: DateTime<Utc> = response.receive_timestamp.into();
+        let transmit: DateTime<Utc> = response.transmit_timestamp.into();
+
+        Sample {
+            offset: ((receive - originate) + (transmit - destination)) / 2,
+            delay: (destination - originate) - (transmit - receive),
+            stratum: response.stratum,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn timestamps_survive_the_era_rollover() {
+        for utc in [
+            Utc.with_ymd_and_hms(2026, 10, 17, 12, 34, 56).unwrap(),
+            Utc.with_ymd_and_hms(2036, 2, 7, 6, 28, 15).unwrap(),
+            Utc.with_ymd_and_hms(2036, 2, 7, 6, 28, 16).unwrap(),
+            Utc.with_ymd_and_hms(2040, 1, 1, 0, 0, 0).unwrap(),
+        ] {
+            let timestamp = Timestamp::from(utc);
+            assert_eq!(DateTime::<Utc>::from(timestamp), utc);
+        }
+
+        let rollover = Timestamp {
+            seconds: 0,
+            fraction: 0,
+        };
+        assert_eq!(
+            DateTime::<Utc>::from(rollover),
+            Utc.with_ymd_and_hms(2036, 2, 7, 6, 28, 16).unwrap()
+        );
+    }
+}
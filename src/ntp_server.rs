@@ -0,0 +1,132 @@
+use std::io::Cursor;
+
+use binrw::{BinRead, BinWrite};
+use chrono::{DateTime, Utc};
+use tokio::net::UdpSocket;
+
+use crate::{
+    ntp::{Packet, Timestamp, LEAP_UNKNOWN, MODE_CLIENT, MODE_SERVER},
+    time_source,
+};
+
+/// Stratum of the local clock when no `--ntp-server` is configured, like ntpd's local clock driver.
+const LOCAL_STRATUM: u8 = 10;
+
+/// Tells clients not to use this server until it synchronized.
+const UNSYNCHRONIZED_STRATUM: u8 = 16;
+
+/// About a microsecond, as log2 seconds.
+const PRECISION: i8 = -20;
+
+/// 16.16 fixed point seconds.
+fn short_format(delta: chrono::TimeDelta) -> u32 {
+    let micros = delta.num_microseconds().unwrap_or_default().max(0) as u64;
+    ((micros << 16) / 1_000_000) as u32
+}
+
+/// The answer to `request`, received at `receive` by `time_source::now`.
+///
+/// `None` for anything but a client request, so that two servers never keep answering each other.
+fn answer(request: &Packet, receive: DateTime<Utc>) -> Option<Packet> {
+    if request.flags.mode() != MODE_CLIENT || !(1..=4).contains(&request.flags.version()) {
+        return None;
+    }
+
+    let (leap, stratum, reference_id, reference_timestamp, root_delay) =
+        match (time_source::server(), time_source::last_sync()) {
+            (None, _) => (0, LOCAL_STRATUM, *b"LOCL", Timestamp::default(), 0),
+            (Some(_), None) => (
+                LEAP_UNKNOWN,
+                UNSYNCHRONIZED_STRATUM,
+                *b"INIT",
+                Timestamp::default(),
+                0,
+            ),
+            (Some(_), Some(sync)) => (
+                0,
+                sync.stratum
+                    .saturating_add(1)
+                    .min(UNSYNCHRONIZED_STRATUM - 1),
+                sync.reference_id,
+                sync.utc.into(),
+                short_format(sync.delay),
+            ),
+        };
+
+    Some(Packet {
+        flags: request.flags.with_leap(leap).with_mode(MODE_SERVER),
+        stratum,
+        poll: request.poll,
+        precision: PRECISION,
+        root_delay,
+        root_dispersion: 0,
+        reference_id,
+        reference_timestamp,
+        originate_timestamp: request.transmit_timestamp,
+        receive_timestamp: receive.into(),
+        transmit_timestamp: time_source::now().into(),
+    })
+}
+
+/// Answers SNTP requests on `socket` with the time the editions show.
+pub async fn serve(socket: UdpSocket) {
+    // Room for extension fields, which are ignored.
+    let mut buffer = [0u8; 1024];
+
+    loop {
+        let Ok((len, peer)) = socket.recv_from(&mut buffer).await else {
+            continue;
+        };
+
+        let receive = time_source::now();
+
+        let Some(response) = Packet::read(&mut Cursor::new(&buffer[..len]))
+            .ok()
+            .and_then(|request| answer(&request, receive))
+        else {
+            continue;
+        };
+
+        let mut packet = Cursor::new(vec![]);
+        response.write(&mut packet).unwrap();
+
+        if let Err(e) = socket.send_to(packet.get_ref(), peer).await {
+            tracing::warn!("failed to answer {peer}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_with_the_time_source() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server.local_addr().unwrap()).await.unwrap();
+
+        tokio::spawn(serve(server));
+
+        let sample = time_source::query(&client, None).await.unwrap();
+
+        assert!(
+            sample.offset.abs() < TimeDelta::milliseconds(50),
+            "{sample:?}"
+        );
+        assert_eq!(sample.stratum, LOCAL_STRATUM);
+    }
+
+    #[test]
+    fn ignores_everything_but_client_requests() {
+        let now = Utc::now();
+        let request = Packet::request(now);
+
+        let response = answer(&request, now).unwrap();
+        assert_eq!(response.flags.mode(), MODE_SERVER);
+        assert_eq!(response.originate_timestamp, request.transmit_timestamp);
+        assert!(answer(&response, now).is_none());
+    }
+}
@@ -0,0 +1,58 @@
+use std::{net::SocketAddr, time::Duration};
+
+use axum::{extract::connect_info::Connected, serve::IncomingStream};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::tls::TlsListener;
+
+/// What is known about the other end of a connection when it's accepted.
+#[derive(Debug, Clone, Copy)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    /// Smoothed round trip time the kernel measured, so far only the handshake.
+    pub rtt: Option<Duration>,
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Peer {
+            addr: *stream.remote_addr(),
+            rtt: rtt(stream.io()),
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        Peer {
+            addr: *stream.remote_addr(),
+            rtt: rtt(stream.io().get_ref().0),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn rtt(stream: &TcpStream) -> Option<Duration> {
+    use std::os::fd::AsRawFd;
+
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut length = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+
+    // SAFETY: `info` and `length` describe a buffer which is valid for the whole call.
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut length,
+        )
+    };
+
+    (result == 0 && info.tcpi_rtt != 0).then(|| Duration::from_micros(info.tcpi_rtt.into()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rtt(_stream: &TcpStream) -> Option<Duration> {
+    None
+}
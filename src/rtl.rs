@@ -1,9 +1,16 @@
-use crate::{model::Context, Clock, ConnectionCounter};
+use crate::{
+    connection_counter::Edition,
+    limits::Admission,
+    metrics,
+    model::{render_head, ClockQuery, Context},
+    peer::Peer,
+    Clock, ClockRegistry, ConnectionCounter,
+};
 
 use async_stream::try_stream;
 use axum::{
     body::Body,
-    extract::State,
+    extract::{ConnectInfo, Query, State},
     http::{header, HeaderMap, HeaderName},
     response::IntoResponse,
 };
@@ -12,12 +19,17 @@ use futures::Stream;
 use itertools::Itertools;
 
 pub fn encode(ctx: &Context) -> Bytes {
-    let jst = ctx.jst.as_str();
-    let connection_count = ctx.connection_count;
+    let time = ctx.local_time();
+    let zone = ctx.zone_label();
+    let connection_count = ctx.connections.total;
+    let edition_count = ctx.connections.edition(Edition::Rtl);
 
-    let s = format!("     {jst} )JST( / {connection_count} active connection)s(.     ;8328#&");
+    let s = match ctx.reload_after {
+        Some(reload_after) => format!("     Server restarting, reload in {reload_after} seconds.     ;8328#&"),
+        None => format!("     {time} ){zone}( / {edition_count} on this edition, {connection_count} total.     ;8328#&"),
+    };
 
-    let buf = itertools::rev(s.into_bytes().into_iter()).collect_vec();
+    let buf = itertools::rev(s.into_bytes()).collect_vec();
 
     
 
@@ -29,12 +41,15 @@ fn stream(
     counter: ConnectionCounter,
 ) -> impl Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Send + Sync>>> {
     try_stream! {
-        let _session = counter.acquire();
-        yield Bytes::from_static(include_bytes!("../assets/rtl_head.html"));
+        let _session = counter.acquire(Edition::Rtl, clock.key().tz);
+        let zone = clock.borrow().zone.clone();
+        yield render_head(include_str!("../assets/rtl_head.html"), &zone);
         clock.mark_unchanged();
 
         loop {
-            let _ = clock.changed().await;
+            if clock.changed().await.is_err() {
+                break;
+            }
             let partial = clock.borrow_and_update().rtl.clone();
             yield partial;
         }
@@ -43,10 +58,16 @@ fn stream(
 
 pub async fn handler(
     headers: HeaderMap,
-    State((clock, counter)): State<(Clock, ConnectionCounter)>,
+    Query(query): Query<ClockQuery>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    admission: Admission,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
 ) -> impl IntoResponse {
-    let stream = stream(clock, counter);
-    let body = Body::from_stream(stream);
+    let stream = stream(
+        clocks.subscribe(clocks.key(query.tz), &peer, admission.deadline()),
+        counter,
+    );
+    let body = Body::from_stream(metrics::count_bytes(Edition::Rtl, admission.limit(stream)));
 
     let is_cloudflare = headers.contains_key("cf-ray");
 
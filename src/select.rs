@@ -1,9 +1,16 @@
-use crate::{model::Context, Clock, ConnectionCounter};
+use crate::{
+    connection_counter::Edition,
+    limits::Admission,
+    metrics,
+    model::{render_head, ClockQuery, Context},
+    peer::Peer,
+    Clock, ClockRegistry, ConnectionCounter,
+};
 
 use async_stream::try_stream;
 use axum::{
     body::Body,
-    extract::State,
+    extract::{ConnectInfo, Query, State},
     http::{header, HeaderMap, HeaderName},
     response::IntoResponse,
 };
@@ -11,11 +18,19 @@ use bytes::Bytes;
 use futures::Stream;
 
 pub fn encode(ctx: &Context) -> Bytes {
-    let jst = ctx.jst.as_str();
-    let connection_count = ctx.connection_count;
+    let time = ctx.local_time();
+    let zone = ctx.zone_label();
+    let connection_count = ctx.connections.total;
+    let edition_count = ctx.connections.edition(Edition::Select);
+
+    if let Some(reload_after) = ctx.reload_after {
+        return bytes::Bytes::from(format!(
+            "<option selected>Server restarting, reload in {reload_after} seconds.</option>\n"
+        ));
+    }
 
     bytes::Bytes::from(format!(
-        "<option selected>{jst} (JST) / {connection_count} active connection(s).</option>\n"
+        "<option selected>{time} ({zone}) / {edition_count} on this edition, {connection_count} total.</option>\n"
     ))
 }
 
@@ -24,12 +39,15 @@ fn stream(
     counter: ConnectionCounter,
 ) -> impl Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Send + Sync>>> {
     try_stream! {
-        let _session = counter.acquire();
-        yield Bytes::from_static(include_bytes!("../assets/select_head.html"));
+        let _session = counter.acquire(Edition::Select, clock.key().tz);
+        let zone = clock.borrow().zone.clone();
+        yield render_head(include_str!("../assets/select_head.html"), &zone);
         clock.mark_unchanged();
 
         loop {
-            let _ = clock.changed().await;
+            if clock.changed().await.is_err() {
+                break;
+            }
             let partial = clock.borrow_and_update().select.clone();
             yield partial;
         }
@@ -38,10 +56,19 @@ fn stream(
 
 pub async fn handler(
     headers: HeaderMap,
-    State((clock, counter)): State<(Clock, ConnectionCounter)>,
+    Query(query): Query<ClockQuery>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    admission: Admission,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
 ) -> impl IntoResponse {
-    let stream = stream(clock, counter);
-    let body = Body::from_stream(stream);
+    let stream = stream(
+        clocks.subscribe(clocks.key(query.tz), &peer, admission.deadline()),
+        counter,
+    );
+    let body = Body::from_stream(metrics::count_bytes(
+        Edition::Select,
+        admission.limit(stream),
+    ));
 
     let is_cloudflare = headers.contains_key("cf-ray");
 
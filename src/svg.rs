@@ -1,9 +1,16 @@
-use crate::{model::Context, Clock, ConnectionCounter};
+use crate::{
+    connection_counter::Edition,
+    limits::Admission,
+    metrics,
+    model::{render_head, ClockKey, ClockQuery, Context},
+    peer::Peer,
+    Clock, ClockRegistry, ConnectionCounter,
+};
 
 use async_stream::try_stream;
 use axum::{
     body::Body,
-    extract::State,
+    extract::{ConnectInfo, Query, State},
     http::{header, HeaderMap, HeaderName},
     response::IntoResponse,
 };
@@ -11,9 +18,19 @@ use bytes::Bytes;
 use futures::Stream;
 
 pub fn encode(ctx: &Context) -> Bytes {
-    let jst = ctx.jst.as_str();
+    let time = ctx.local_time();
+    let zone = ctx.zone_label();
     let timestamp = ctx.timestamp;
-    let connection_count = ctx.connection_count;
+    let connection_count = ctx.connections.total;
+    let edition_count = ctx.connections.edition(Edition::Svg);
+
+    if let Some(reload_after) = ctx.reload_after {
+        return bytes::Bytes::from(format!("
+    <rect x=\"0\" y=\"0\" width=\"320\" height=\"120\" fill=\"black\" />
+    <text font-size=\"1.5em\" x=\"160\" y=\"40\" text-anchor=\"middle\" dominant-baseline=\"middle\" fill=\"white\">Server restarting</text>
+    <text font-size=\"0.75em\" x=\"160\" y=\"70\" text-anchor=\"middle\" dominant-baseline=\"middle\" fill=\"white\">Reload in {reload_after} seconds</text>
+    "));
+    }
 
     let user_emojis: String = if connection_count <= 20 {
         "👤".repeat(connection_count)
@@ -21,9 +38,15 @@ pub fn encode(ctx: &Context) -> Bytes {
         "👤👤👤👤👤👤👤👤👤👤👤👤👤👤👤👤👤👤👤👤..".to_string()
     };
 
+    let sync = match ctx.sync_note() {
+        Some(note) => format!("<text font-size=\"0.4em\" x=\"160\" y=\"112\" text-anchor=\"middle\" dominant-baseline=\"middle\" fill=\"gray\">{note}</text>"),
+        None => String::new(),
+    };
+
     bytes::Bytes::from(format!("
     <rect x=\"0\" y=\"0\" width=\"320\" height=\"120\" fill=\"black\" />
-    <text font-size=\"2em\" x=\"160\" y=\"40\" text-anchor=\"middle\" dominant-baseline=\"middle\" fill=\"white\">{jst}</text>
+    <text font-size=\"2em\" x=\"160\" y=\"40\" text-anchor=\"middle\" dominant-baseline=\"middle\" fill=\"white\">{time}</text>
+    <text font-size=\"0.75em\" x=\"160\" y=\"62\" text-anchor=\"middle\" dominant-baseline=\"middle\" fill=\"white\">{zone}</text>
     <defs>
         <clipPath id=\"clip{timestamp}\">
             <text font-size=\"0.5em\" x=\"160\" y=\"80\" text-anchor=\"middle\" dominant-baseline=\"middle\" fill=\"white\">\
@@ -32,6 +55,8 @@ pub fn encode(ctx: &Context) -> Bytes {
         </clipPath>
     </defs>
     <rect x=\"0\" y=\"0\" width=\"320\" height=\"120\" fill=\"white\" clip-path=\"url(#clip{timestamp})\"/>
+    <text font-size=\"0.5em\" x=\"160\" y=\"100\" text-anchor=\"middle\" dominant-baseline=\"middle\" fill=\"white\">{edition_count} on this edition, {connection_count} total</text>
+    {sync}
     "))
 }
 
@@ -40,12 +65,15 @@ fn stream(
     counter: ConnectionCounter,
 ) -> impl Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Send + Sync>>> {
     try_stream! {
-        let _session = counter.acquire();
-        yield Bytes::from_static(include_bytes!("../assets/svg_head.html"));
+        let _session = counter.acquire(Edition::Svg, clock.key().tz);
+        let zone = clock.borrow().zone.clone();
+        yield render_head(include_str!("../assets/svg_head.html"), &zone);
         clock.mark_unchanged();
 
         loop {
-            let _ = clock.changed().await;
+            if clock.changed().await.is_err() {
+                break;
+            }
             let partial = clock.borrow_and_update().svg.clone();
             yield partial;
         }
@@ -54,10 +82,18 @@ fn stream(
 
 pub async fn handler(
     headers: HeaderMap,
-    State((clock, counter)): State<(Clock, ConnectionCounter)>,
+    Query(query): Query<ClockQuery>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    admission: Admission,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
 ) -> impl IntoResponse {
-    let stream = stream(clock, counter);
-    let body = Body::from_stream(stream);
+    let key = ClockKey {
+        precision: query.precision.unwrap_or_default(),
+        ..clocks.key(query.tz)
+    };
+
+    let stream = stream(clocks.subscribe(key, &peer, admission.deadline()), counter);
+    let body = Body::from_stream(metrics::count_bytes(Edition::Svg, admission.limit(stream)));
 
     let is_cloudflare = headers.contains_key("cf-ray");
 
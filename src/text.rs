@@ -0,0 +1,264 @@
+use crate::{
+    connection_counter::Edition,
+    font,
+    layout::{Layout, LINE_GAP},
+    limits::Admission,
+    metrics,
+    model::Context,
+    mygif::Size,
+    peer::Peer,
+    Clock, ClockRegistry, ConnectionCounter,
+};
+
+use async_stream::try_stream;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, HeaderName},
+    response::IntoResponse,
+};
+use bytes::Bytes;
+use chrono_tz::Tz;
+use futures::Stream;
+use serde::Deserialize;
+
+/// Width of the big digits in terminal columns, one pixel each.
+const COLUMNS: u16 = 80;
+
+/// Bright white, cyan and yellow, cycling per line like the banner inks.
+const ANSI_COLORS: [u8; 3] = [97, 96, 93];
+
+/// Clears the screen and hides the cursor.
+const HEAD: &str = "\x1b[2J\x1b[?25l";
+
+/// Clients which show the body as is, rather than rendering HTML.
+const TERMINAL_AGENTS: [&str; 3] = ["curl/", "wget/", "httpie/"];
+
+#[derive(Debug, Deserialize)]
+pub struct TextQuery {
+    tz: Option<Tz>,
+    /// Big digits, enabled by default.
+    big: Option<bool>,
+}
+
+pub fn is_terminal(headers: &HeaderMap) -> bool {
+    let Some(agent) = headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+
+    let agent = agent.to_ascii_lowercase();
+    TERMINAL_AGENTS.iter().any(|prefix| agent.starts_with(prefix))
+}
+
+/// Draws `text` with half blocks, each character covering two pixel rows.
+fn big_digits(text: &str) -> String {
+    let font = font::get();
+    let wide = Size::new(COLUMNS, u16::MAX / 2);
+
+    // Lay out once to count the lines, then again on a screen exactly that high.
+    let lines = Layout::centered(font, wide, text).lines.len() as u16;
+    let screen = Size::new(
+        COLUMNS,
+        (lines * (font.size.height + LINE_GAP)).saturating_sub(LINE_GAP),
+    );
+
+    let canvas = Layout::centered(font, screen, text).paint(screen, |ink| 1 + ink as u8);
+    let width = screen.width as usize;
+
+    let mut art = String::new();
+
+    for rows in canvas.chunks(width * 2) {
+        let (top, bottom) = rows.split_at(width.min(rows.len()));
+
+        if let Some(ink) = rows.iter().find(|v| **v != 0) {
+            let color = ANSI_COLORS[(*ink as usize - 1) % ANSI_COLORS.len()];
+            art.push_str(&format!("\x1b[{color}m"));
+        }
+
+        for (x, v) in top.iter().enumerate() {
+            let is_top = *v != 0;
+            let is_bottom = bottom.get(x).is_some_and(|v| *v != 0);
+
+            art.push(match (is_top, is_bottom) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            });
+        }
+
+        art.push_str("\x1b[0m\x1b[K\n");
+    }
+
+    art
+}
+
+fn encode_with(ctx: &Context, big: bool) -> Bytes {
+    let time = ctx.local_time();
+    let zone = ctx.zone_label();
+    let connection_count = ctx.connections.total;
+    let edition_count = ctx.connections.edition(Edition::Text);
+
+    // Shows the cursor again, as nothing follows.
+    if let Some(reload_after) = ctx.reload_after {
+        return Bytes::from(format!(
+            "\x1b[H\x1b[J\x1b[1mServer restarting\x1b[0m, try again in {reload_after} seconds.\n\x1b[?25h"
+        ));
+    }
+
+    let art = if big {
+        big_digits(&time) + "\x1b[K\n"
+    } else {
+        String::new()
+    };
+
+    let sync = match ctx.sync_note() {
+        Some(note) => format!("{note}.\x1b[K\n"),
+        None => String::new(),
+    };
+
+    Bytes::from(format!(
+        "\x1b[H{art}\x1b[1m{time}\x1b[0m ({zone})\x1b[K\n{edition_count} on this edition, {connection_count} total.\x1b[K\n{sync}\x1b[J"
+    ))
+}
+
+pub fn encode(ctx: &Context) -> Bytes {
+    encode_with(ctx, false)
+}
+
+pub fn encode_big(ctx: &Context) -> Bytes {
+    encode_with(ctx, true)
+}
+
+fn stream(
+    mut clock: Clock,
+    counter: ConnectionCounter,
+    big: bool,
+) -> impl Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Send + Sync>>> {
+    try_stream! {
+        let _session = counter.acquire(Edition::Text, clock.key().tz);
+        yield Bytes::from_static(HEAD.as_bytes());
+
+        loop {
+            let partial = {
+                let data = clock.borrow_and_update();
+                if big { data.text_big.clone() } else { data.text.clone() }
+            };
+
+            yield partial;
+            if clock.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+pub async fn handler(
+    headers: HeaderMap,
+    Query(query): Query<TextQuery>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    admission: Admission,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
+) -> impl IntoResponse {
+    let stream = stream(
+        clocks.subscribe(clocks.key(query.tz), &peer, admission.deadline()),
+        counter,
+        query.big.unwrap_or(true),
+    );
+    let body = Body::from_stream(metrics::count_bytes(Edition::Text, admission.limit(stream)));
+
+    let is_cloudflare = headers.contains_key("cf-ray");
+
+    let headers = [
+        (
+            header::CONTENT_TYPE,
+            if is_cloudflare {
+                "application/grpc"
+            } else {
+                "text/plain; charset=utf-8"
+            },
+        ),
+        (
+            HeaderName::from_static("x-original-content-type"),
+            "text/plain; charset=utf-8",
+        ),
+    ];
+
+    (headers, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderValue, Uri};
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::gif_banner::tests::{context, setup};
+    use crate::model::Banner;
+
+    const BLOCKS: [char; 3] = ['▀', '▄', '█'];
+
+    fn agent(value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(header::USER_AGENT, HeaderValue::from_static(value))])
+    }
+
+    fn ctx() -> Context {
+        context(
+            Utc.with_ymd_and_hms(2026, 10, 17, 12, 34, 56).unwrap(),
+            Banner {
+                size: Size::new(88, 31),
+                scale: 1,
+            },
+        )
+    }
+
+    #[test]
+    fn terminals_are_told_apart_by_user_agent() {
+        assert!(is_terminal(&agent("curl/8.10.1")));
+        assert!(is_terminal(&agent("Wget/1.24.5")));
+        assert!(is_terminal(&agent("HTTPie/3.2.4")));
+        assert!(!is_terminal(&agent(
+            "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0"
+        )));
+        assert!(!is_terminal(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn big_digits_pack_two_pixel_rows_per_line() {
+        setup();
+
+        let art = big_digits("12:34");
+        let lines: Vec<&str> = art.lines().collect();
+
+        assert_eq!(lines.len(), font::get().size.height.div_ceil(2) as usize);
+
+        for line in lines {
+            let cells = line.chars().filter(|c| *c == ' ' || BLOCKS.contains(c));
+            assert_eq!(cells.count(), COLUMNS as usize, "{line:?}");
+            assert!(line.ends_with("\x1b[0m\x1b[K"), "{line:?}");
+        }
+
+        assert!(art.contains(BLOCKS));
+    }
+
+    #[test]
+    fn big_can_be_turned_off() {
+        setup();
+
+        let query: Query<TextQuery> =
+            Query::try_from_uri(&Uri::from_static("/text?big=false")).unwrap();
+        assert_eq!(query.big, Some(false));
+
+        let ctx = ctx();
+        let plain = String::from_utf8(encode(&ctx).to_vec()).unwrap();
+        let big = String::from_utf8(encode_big(&ctx).to_vec()).unwrap();
+
+        assert!(plain.starts_with("\x1b[H\x1b[1m2026-10-17 12:34:56\x1b[0m (UTC)"));
+        assert!(!plain.contains(BLOCKS));
+        assert!(big.contains(BLOCKS));
+        assert!(big.ends_with(&plain["\x1b[H".len()..]));
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::RwLock,
+    time::Duration,
+};
+
+use binrw::{BinRead, BinWrite};
+use chrono::{DateTime, TimeDelta, Utc};
+use once_cell::sync::OnceCell;
+use tokio::net::UdpSocket;
+
+use crate::ntp::{Packet, Sample, LEAP_UNKNOWN, MODE_SERVER, PACKET_SIZE};
+
+pub const DEFAULT_PORT: u16 = 123;
+
+/// How long to wait for an answer before giving up until the next interval.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Larger offsets are still applied but warned about, like ntpd's panic threshold.
+const LARGE_OFFSET: TimeDelta = TimeDelta::seconds(1000);
+
+/// The highest stratum of a synchronized server.
+const MAX_STRATUM: u8 = 15;
+
+static SERVER: OnceCell<String> = OnceCell::new();
+
+static LAST_SYNC: RwLock<Option<Measurement>> = RwLock::new(None);
+
+/// The last successful exchange with the SNTP server.
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    /// When it happened, already corrected by `offset`.
+    pub utc: DateTime<Utc>,
+    pub offset: TimeDelta,
+    pub delay: TimeDelta,
+    /// Of the server.
+    pub stratum: u8,
+    /// Identifies the server to its clients, its address if IPv4.
+    pub reference_id: [u8; 4],
+}
+
+/// The local clock corrected by the offset measured last, if any.
+pub fn now() -> DateTime<Utc> {
+    Utc::now() + offset()
+}
+
+pub fn offset() -> TimeDelta {
+    last_sync().map(|sync| sync.offset).unwrap_or_default()
+}
+
+pub fn last_sync() -> Option<Measurement> {
+    *LAST_SYNC.read().unwrap()
+}
+
+pub fn server() -> Option<&'static str> {
+    SERVER.get().map(String::as_str)
+}
+
+/// Asks the server `socket` is connected to for its time once.
+///
+/// Offsets beyond `max_offset` are taken for a broken server rather than a broken host.
+pub async fn query(socket: &UdpSocket, max_offset: Option<TimeDelta>) -> io::Result<Sample> {
+    let originate = Utc::now();
+    let request = Packet::request(originate);
+
+    let mut buffer = io::Cursor::new(vec![]);
+    request.write(&mut buffer).unwrap();
+    socket.send(buffer.get_ref()).await?;
+
+    let mut response = [0u8; PACKET_SIZE];
+
+    // Anything which doesn't answer our request is ignored, like a late answer to an earlier one.
+    let response = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            let len = socket.recv(&mut response).await?;
+            let destination = Utc::now();
+
+            let Ok(packet) = Packet::read(&mut io::Cursor::new(&response[..len])) else {
+                continue;
+            };
+
+            if packet.flags.mode() == MODE_SERVER
+                && packet.originate_timestamp == request.transmit_timestamp
+            {
+                return Ok::<_, io::Error>((packet, destination));
+            }
+        }
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no answer from the SNTP server"))?;
+
+    let (packet, destination) = response?;
+
+    // Stratum 0 is a kiss-o'-death, e.g. when asking too often, and above 15 unsynchronized.
+    if packet.stratum == 0
+        || packet.stratum > MAX_STRATUM
+        || packet.flags.leap() == LEAP_UNKNOWN
+        || packet.transmit_timestamp.is_zero()
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the SNTP server isn't synchronized",
+        ));
+    }
+
+    let sample = Sample::new(&packet, originate, destination);
+
+    if let Some(max_offset) = max_offset.filter(|max| sample.offset.abs() > *max) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the SNTP server is {}s off, more than {}s",
+                sample.offset.num_seconds(),
+                max_offset.num_seconds()
+            ),
+        ));
+    }
+
+    Ok(sample)
+}
+
+/// `server` is `host:port`, or just a host to use the default port.
+async fn sync(server: &str, max_offset: Option<TimeDelta>) -> io::Result<(Sample, SocketAddr)> {
+    let addr = match tokio::net::lookup_host(server).await {
+        Ok(mut addrs) => addrs.next(),
+        Err(_) => tokio::net::lookup_host((server, DEFAULT_PORT))
+            .await?
+            .next(),
+    }
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for the SNTP server"))?;
+
+    let local: SocketAddr = match addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(addr).await?;
+
+    Ok((query(&socket, max_offset).await?, addr))
+}
+
+/// Keeps correcting `now` with the offset to `server`, measured every `interval`.
+pub fn initialization(server: String, interval: Duration, max_offset: Option<Duration>) {
+    let max_offset = max_offset.map(|v| TimeDelta::from_std(v).unwrap());
+
+    SERVER.set(server).unwrap();
+
+    tokio::spawn(async move {
+        let server = SERVER.get().unwrap();
+
+        loop {
+            match sync(server, max_offset).await {
+                Ok((sample, addr)) => {
+                    if sample.offset.abs() > LARGE_OFFSET {
+                        tracing::warn!(
+                            "the local clock is {}s off {server}, showing the time of the server",
+                            -sample.offset.num_seconds()
+                        );
+                    } else {
+                        tracing::info!(
+                            "synced with {server}: offset {}ms, delay {}ms",
+                            sample.offset.num_milliseconds(),
+                            sample.delay.num_milliseconds()
+                        );
+                    }
+
+                    *LAST_SYNC.write().unwrap() = Some(Measurement {
+                        utc: Utc::now() + sample.offset,
+                        offset: sample.offset,
+                        delay: sample.delay,
+                        stratum: sample.stratum,
+                        reference_id: match addr {
+                            SocketAddr::V4(addr) => addr.ip().octets(),
+                            SocketAddr::V6(_) => [0; 4],
+                        },
+                    });
+                }
+                Err(e) => tracing::warn!("failed to sync with {server}: {e}"),
+            }
+
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ntp::{Flags, VERSION};
+
+    /// Answers one request as a server whose clock is `offset` ahead.
+    async fn stand_in(offset: TimeDelta, stratum: u8) -> UdpSocket {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server.local_addr().unwrap()).await.unwrap();
+
+        tokio::spawn(async move {
+            let mut request = [0u8; PACKET_SIZE];
+            let (len, peer) = server.recv_from(&mut request).await.unwrap();
+            let request = Packet::read(&mut io::Cursor::new(&request[..len])).unwrap();
+
+            let receive = Utc::now() + offset;
+
+            let response = Packet {
+                flags: Flags::new().with_version(VERSION).with_mode(MODE_SERVER),
+                stratum,
+                originate_timestamp: request.transmit_timestamp,
+                receive_timestamp: receive.into(),
+                transmit_timestamp: (Utc::now() + offset).into(),
+                ..Packet::request(receive)
+            };
+
+            let mut buffer = io::Cursor::new(vec![]);
+            response.write(&mut buffer).unwrap();
+            server.send_to(buffer.get_ref(), peer).await.unwrap();
+        });
+
+        client
+    }
+
+    #[tokio::test]
+    async fn measures_the_offset_of_the_server() {
+        for offset in [TimeDelta::seconds(5), TimeDelta::milliseconds(-1500)] {
+            let sample = query(&stand_in(offset, 2).await, None).await.unwrap();
+
+            assert!(
+                (sample.offset - offset).abs() < TimeDelta::milliseconds(50),
+                "{sample:?}"
+            );
+            assert!(sample.delay >= TimeDelta::zero());
+            assert!(sample.delay < TimeDelta::milliseconds(50));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_kiss_o_death() {
+        let error = query(&stand_in(TimeDelta::seconds(5), 0).await, None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn rejects_unsynchronized_servers() {
+        let error = query(&stand_in(TimeDelta::seconds(5), 16).await, None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn rejects_offsets_beyond_the_bound() {
+        let max_offset = Some(TimeDelta::seconds(1000));
+
+        let error = query(&stand_in(TimeDelta::hours(2), 2).await, max_offset)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // Without one, a host that far off is corrected all the same.
+        let sample = query(&stand_in(TimeDelta::hours(2), 2).await, None)
+            .await
+            .unwrap();
+
+        assert!((sample.offset - TimeDelta::hours(2)).abs() < TimeDelta::seconds(1));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{
+    connection_counter::Edition,
+    limits::Admission,
+    metrics,
+    model::{ClockData, ClockQuery},
+    peer::Peer,
+    ClockRegistry, ConnectionCounter,
+};
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Query, State,
+    },
+    response::IntoResponse,
+};
+use chrono::{DateTime, SecondsFormat};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// Longer formats are refused, as each connection has its own formatted every second.
+const MAX_FORMAT_LEN: usize = 64;
+
+#[derive(Debug, Serialize)]
+struct Frame<'a> {
+    timestamp: i64,
+    iso8601: String,
+    zone: &'a str,
+    connection_count: usize,
+    time: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reload_after: Option<u64>,
+}
+
+/// Sent by the client, every field is optional and changes only what it names.
+#[derive(Debug, Deserialize)]
+struct Command {
+    tz: Option<Tz>,
+    format: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Error {
+    error: String,
+}
+
+/// Formatted per connection, as each one may choose its own format.
+fn encode(data: &ClockData, tz: Tz, format: &str) -> String {
+    let local = DateTime::from_timestamp_millis(data.timestamp)
+        .unwrap_or_default()
+        .with_timezone(&tz);
+
+    serde_json::to_string(&Frame {
+        timestamp: data.timestamp,
+        iso8601: local.to_rfc3339_opts(SecondsFormat::Millis, false),
+        zone: &data.zone,
+        connection_count: data.connection_count,
+        time: local.format(format).to_string(),
+        reload_after: data.reload_after,
+    })
+    .unwrap()
+}
+
+fn error_message(error: String) -> Message {
+    Message::Text(serde_json::to_string(&Error { error }).unwrap().into())
+}
+
+fn parse(text: &str) -> Result<Command, String> {
+    let command: Command = serde_json::from_str(text).map_err(|e| e.to_string())?;
+
+    if let Some(format) = &command.format {
+        if format.len() > MAX_FORMAT_LEN {
+            return Err(format!("formats are limited to {MAX_FORMAT_LEN} bytes"));
+        }
+
+        crate::parse_time_format(format)?;
+    }
+
+    Ok(command)
+}
+
+async fn session(
+    mut socket: WebSocket,
+    clocks: ClockRegistry,
+    counter: ConnectionCounter,
+    peer: Peer,
+    tz: Option<Tz>,
+    admission: Admission,
+) {
+    let mut key = clocks.key(tz);
+    let mut _session = counter.acquire(Edition::Ws, key.tz);
+
+    let mut format = clocks.time_format().to_string();
+    let mut clock = clocks.subscribe(key, &peer, admission.deadline());
+
+    let mut is_stale = true;
+
+    loop {
+        if is_stale {
+            let frame = encode(&clock.borrow_and_update(), key.tz, &format);
+            metrics::bytes_sent(Edition::Ws, frame.len());
+
+            if socket.send(Message::Text(frame.into())).await.is_err() {
+                return;
+            }
+        }
+
+        is_stale = tokio::select! {
+            changed = clock.changed() => {
+                if changed.is_err() {
+                    let close = match clock.is_expired() {
+                        true => None,
+                        false => Some(CloseFrame {
+                            code: close_code::RESTART,
+                            reason: "server restarting".into(),
+                        }),
+                    };
+                    let _ = socket.send(Message::Close(close)).await;
+                    return;
+                }
+
+                true
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match parse(&text) {
+                    Ok(command) => {
+                        if let Some(tz) = command.tz {
+                            key = clocks.key(Some(tz));
+                            clock = clocks.subscribe(key, &peer, admission.deadline());
+
+                            // Released first, so that the connection isn't counted twice meanwhile.
+                            drop(_session);
+                            _session = counter.acquire(Edition::Ws, key.tz);
+                        }
+
+                        if let Some(new_format) = command.format {
+                            format = new_format;
+                        }
+
+                        true
+                    }
+                    Err(error) => {
+                        if socket.send(error_message(error)).await.is_err() {
+                            return;
+                        }
+
+                        false
+                    }
+                },
+                Some(Ok(Message::Binary(_))) => {
+                    let error = "commands are JSON text messages".to_string();
+
+                    if socket.send(error_message(error)).await.is_err() {
+                        return;
+                    }
+
+                    false
+                }
+                // Pings are answered by axum.
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => false,
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+            },
+        };
+    }
+}
+
+pub async fn handler(
+    ws: WebSocketUpgrade,
+    Query(query): Query<ClockQuery>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    admission: Admission,
+    State((clocks, counter)): State<(ClockRegistry, ConnectionCounter)>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| session(socket, clocks, counter, peer, query.tz, admission))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_are_validated_and_capped() {
+        let command = parse(r#"{"tz":"Europe/Berlin","format":"%H:%M"}"#).unwrap();
+        assert_eq!(command.tz, Some(chrono_tz::Europe::Berlin));
+        assert_eq!(command.format.as_deref(), Some("%H:%M"));
+
+        assert!(parse(r#"{"format":"%Q"}"#).is_err());
+
+        let format = "%H".repeat(MAX_FORMAT_LEN / 2);
+        assert!(parse(&format!(r#"{{"format":"{format}"}}"#)).is_ok());
+        assert_eq!(
+            parse(&format!(r#"{{"format":"{format}%"}}"#)).unwrap_err(),
+            "formats are limited to 64 bytes"
+        );
+    }
+}